@@ -1,19 +1,49 @@
-use bytemuck::{Pod, Zeroable};
+use bark_protocol::{ChannelCount, MAX_SAMPLES_PER_PACKET};
 
 pub type Sample = f32;
 
-#[derive(Pod, Zeroable, Copy, Clone, Debug)]
-#[repr(C)]
-pub struct Frame(pub Sample, pub Sample);
+/// Interleaved audio buffer large enough to hold a single packet's worth of
+/// audio at any supported channel count
+pub type PacketSamples = [Sample; MAX_SAMPLES_PER_PACKET];
 
 #[derive(Copy, Clone, Debug)]
 #[repr(transparent)]
 pub struct FrameCount(pub usize);
 
-pub fn as_interleaved(frames: &[Frame]) -> &[Sample] {
-    bytemuck::must_cast_slice(frames)
+impl FrameCount {
+    /// Number of whole frames in a buffer of interleaved samples
+    pub fn of(samples: &[Sample], channels: ChannelCount) -> Self {
+        FrameCount(samples.len() / usize::from(channels))
+    }
+
+    pub fn to_sample_count(self, channels: ChannelCount) -> usize {
+        self.0 * usize::from(channels)
+    }
 }
 
-pub fn as_interleaved_mut(frames: &mut [Frame]) -> &mut [Sample] {
-    bytemuck::must_cast_slice_mut(frames)
+/// Copies interleaved audio between buffers of differing channel counts.
+/// Mono input is copied to every output channel, otherwise channels are
+/// mapped one to one, with surplus input channels discarded and surplus
+/// output channels silenced.
+pub fn remap_channels(
+    input: &[Sample],
+    input_channels: ChannelCount,
+    output: &mut [Sample],
+    output_channels: ChannelCount,
+) {
+    let input_channels = usize::from(input_channels);
+    let output_channels = usize::from(output_channels);
+
+    let input_frames = input.chunks_exact(input_channels);
+    let output_frames = output.chunks_exact_mut(output_channels);
+
+    for (input, output) in input_frames.zip(output_frames) {
+        for (channel, sample) in output.iter_mut().enumerate() {
+            *sample = if input_channels == 1 {
+                input[0]
+            } else {
+                input.get(channel).copied().unwrap_or(0.0)
+            };
+        }
+    }
 }
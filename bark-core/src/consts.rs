@@ -1,4 +1,18 @@
 use bark_protocol::FRAMES_PER_PACKET;
 
 pub const MAX_QUEUED_DECODE_SEGMENTS: usize = 48;
+
+/// Largest group of audio packets a single parity packet may cover
+pub const MAX_PARITY_GROUP: usize = 16;
+
+/// Parity packets held by the receive queue, enough to cover every queued
+/// audio packet with the smallest possible groups
+pub const MAX_QUEUED_PARITY: usize = MAX_QUEUED_DECODE_SEGMENTS / 2;
 pub const DECODE_BUFFER_FRAMES: usize = FRAMES_PER_PACKET * 2;
+
+/// Largest ratio of output to stream sample rate we will resample between
+pub const MAX_RESAMPLE_RATIO: usize = 8;
+
+/// Upper bound on the number of frames the receive pipeline will output for
+/// a single packet
+pub const MAX_OUTPUT_FRAMES_PER_PACKET: usize = FRAMES_PER_PACKET * MAX_RESAMPLE_RATIO;
@@ -7,16 +7,18 @@ use core::fmt::Display;
 
 use thiserror::Error;
 
-use bark_protocol::FRAMES_PER_PACKET;
+use bark_protocol::AudioFormat;
 use bark_protocol::packet::Audio;
 use bark_protocol::types::{AudioPacketHeader, AudioPacketFormat};
 
-use crate::audio::Frame;
+use crate::audio::Sample;
 
 #[derive(Debug, Error)]
 pub enum NewDecoderError {
     #[error("unknown format in audio header: {0:?}")]
     UnknownFormat(AudioPacketFormat),
+    #[error("unsupported audio format: {0}")]
+    UnsupportedAudioFormat(AudioFormat),
     #[cfg(feature = "opus")]
     #[error("opus codec error: {0}")]
     Opus(#[from] ::opus::Error),
@@ -35,35 +37,52 @@ pub enum DecodeError {
 
 pub struct Decoder {
     decode: DecodeFormat,
+    format: AudioFormat,
 }
 
-pub type FrameBuffer = [Frame; FRAMES_PER_PACKET];
-
 impl Decoder {
     pub fn new(header: &AudioPacketHeader) -> Result<Self, NewDecoderError> {
+        let format = header.audio_format();
+
+        if !format.is_supported() {
+            return Err(NewDecoderError::UnsupportedAudioFormat(format));
+        }
+
         let decode = match header.format {
             AudioPacketFormat::S16LE => DecodeFormat::S16LE(pcm::S16LEDecoder),
             AudioPacketFormat::F32LE => DecodeFormat::F32LE(pcm::F32LEDecoder),
             #[cfg(feature = "opus")]
-            AudioPacketFormat::OPUS => DecodeFormat::Opus(opus::OpusDecoder::new()?),
+            AudioPacketFormat::OPUS => DecodeFormat::Opus(opus::OpusDecoder::new(format)?),
             format => { return Err(NewDecoderError::UnknownFormat(format)) }
         };
 
-        Ok(Decoder { decode })
+        Ok(Decoder { decode, format })
     }
 
     pub fn describe(&self) -> impl Display + '_ {
         &self.decode as &dyn Display
     }
 
-    pub fn decode(&mut self, packet: Option<&Audio>, out: &mut FrameBuffer) -> Result<(), DecodeError> {
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Decodes a single packet of interleaved audio into `out`, which must be
+    /// exactly one packet long for the stream's audio format
+    pub fn decode(&mut self, packet: Option<&Audio>, out: &mut [Sample]) -> Result<(), DecodeError> {
+        let expected = self.format.samples_per_packet();
+
+        if out.len() != expected {
+            return Err(DecodeError::WrongFrameCount { frames: out.len(), expected });
+        }
+
         let bytes = packet.map(|packet| packet.buffer_bytes());
         self.decode.decode_packet(bytes, out)
     }
 }
 
 trait Decode: Display {
-    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut FrameBuffer) -> Result<(), DecodeError>;
+    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut [Sample]) -> Result<(), DecodeError>;
 }
 
 enum DecodeFormat {
@@ -74,7 +93,7 @@ enum DecodeFormat {
 }
 
 impl Decode for DecodeFormat {
-    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut FrameBuffer) -> Result<(), DecodeError> {
+    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut [Sample]) -> Result<(), DecodeError> {
         match self {
             DecodeFormat::S16LE(dec) => dec.decode_packet(bytes, out),
             DecodeFormat::F32LE(dec) => dec.decode_packet(bytes, out),
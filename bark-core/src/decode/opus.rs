@@ -1,23 +1,27 @@
 use core::fmt::{self, Display};
 
-use bark_protocol::SAMPLE_RATE;
+use bark_protocol::{AudioFormat, ChannelCount};
 
-use crate::audio;
+use crate::audio::{FrameCount, Sample};
 
-use super::{Decode, DecodeError, FrameBuffer};
+use super::{Decode, DecodeError, NewDecoderError};
 
 pub struct OpusDecoder {
     opus: opus::Decoder,
+    channels: ChannelCount,
 }
 
 impl OpusDecoder {
-    pub fn new() -> Result<Self, opus::Error> {
-        let opus = opus::Decoder::new(
-            SAMPLE_RATE.0,
-            opus::Channels::Stereo,
-        )?;
+    pub fn new(format: AudioFormat) -> Result<Self, NewDecoderError> {
+        let channels = match format.channels.0 {
+            1 => opus::Channels::Mono,
+            2 => opus::Channels::Stereo,
+            _ => { return Err(NewDecoderError::UnsupportedAudioFormat(format)); }
+        };
+
+        let opus = opus::Decoder::new(format.sample_rate.0, channels)?;
 
-        Ok(OpusDecoder { opus })
+        Ok(OpusDecoder { opus, channels: format.channels })
     }
 }
 
@@ -28,12 +32,12 @@ impl Display for OpusDecoder {
 }
 
 impl Decode for OpusDecoder {
-    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut FrameBuffer) -> Result<(), DecodeError> {
-        let expected = out.len();
+    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut [Sample]) -> Result<(), DecodeError> {
+        let expected = FrameCount::of(out, self.channels).0;
 
         let frames = match bytes {
-            Some(bytes) => self.opus.decode_float(bytes, audio::as_interleaved_mut(out), false)?,
-            None => self.opus.decode_float(&[], audio::as_interleaved_mut(out), true)?,
+            Some(bytes) => self.opus.decode_float(bytes, out, false)?,
+            None => self.opus.decode_float(&[], out, true)?,
         };
 
         if expected != frames {
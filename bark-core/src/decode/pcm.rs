@@ -1,8 +1,8 @@
 use core::fmt::{self, Display};
 
-use crate::audio;
+use crate::audio::Sample;
 
-use super::{Decode, DecodeError, FrameBuffer};
+use super::{Decode, DecodeError};
 
 pub struct S16LEDecoder;
 
@@ -13,7 +13,7 @@ impl Display for S16LEDecoder {
 }
 
 impl Decode for S16LEDecoder {
-    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut FrameBuffer) -> Result<(), DecodeError> {
+    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut [Sample]) -> Result<(), DecodeError> {
         decode_packed(bytes, out, |bytes| {
             let input = i16::from_le_bytes(bytes);
             let scale = i16::MAX as f32;
@@ -31,28 +31,26 @@ impl Display for F32LEDecoder {
 }
 
 impl Decode for F32LEDecoder {
-    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut FrameBuffer) -> Result<(), DecodeError> {
+    fn decode_packet(&mut self, bytes: Option<&[u8]>, out: &mut [Sample]) -> Result<(), DecodeError> {
         decode_packed(bytes, out, f32::from_le_bytes)
     }
 }
 
 fn decode_packed<const N: usize>(
     bytes: Option<&[u8]>,
-    out: &mut FrameBuffer,
+    out: &mut [Sample],
     func: impl Fn([u8; N]) -> f32,
 ) -> Result<(), DecodeError> {
-    let out_samples = audio::as_interleaved_mut(out);
-
     let Some(bytes) = bytes else {
         // PCM codecs have no packet loss correction
         // just zero fill and return
-        out_samples.fill(0.0);
+        out.fill(0.0);
         return Ok(());
     };
 
-    check_length(bytes, out_samples.len() * N)?;
+    check_length(bytes, out.len() * N)?;
 
-    for (input, output) in bytes.chunks_exact(N).zip(out_samples) {
+    for (input, output) in bytes.chunks_exact(N).zip(out) {
         // when array_chunks stabilises we can use that instead
         // but for now use try_into to turn a &[u8] (guaranteed len == width)
         // into a [u8; width]
@@ -5,13 +5,16 @@ pub mod pcm;
 
 use core::fmt::Display;
 
+use bark_protocol::AudioFormat;
 use bark_protocol::types::AudioPacketFormat;
 use thiserror::Error;
 
-use crate::audio::Frame;
+use crate::audio::Sample;
 
 #[derive(Debug, Error)]
 pub enum NewEncoderError {
+    #[error("unsupported audio format for encoder: {0}")]
+    UnsupportedAudioFormat(AudioFormat),
     #[cfg(feature = "opus")]
     #[error("opus codec error: {0}")]
     Opus(#[from] ::opus::Error),
@@ -28,5 +31,5 @@ pub enum EncodeError {
 
 pub trait Encode: Display + Send {
     fn header_format(&self) -> AudioPacketFormat;
-    fn encode_packet(&mut self, frames: &[Frame], out: &mut [u8]) -> Result<usize, EncodeError>;
+    fn encode_packet(&mut self, samples: &[Sample], out: &mut [u8]) -> Result<usize, EncodeError>;
 }
@@ -1,8 +1,8 @@
 use core::fmt::{self, Display};
 
-use bark_protocol::{types::AudioPacketFormat, SAMPLE_RATE};
+use bark_protocol::{types::AudioPacketFormat, AudioFormat};
 
-use crate::audio::{Frame, self};
+use crate::audio::Sample;
 
 use super::{Encode, EncodeError, NewEncoderError};
 
@@ -11,10 +11,23 @@ pub struct OpusEncoder {
 }
 
 impl OpusEncoder {
-    pub fn new() -> Result<Self, NewEncoderError> {
+    pub fn new(format: AudioFormat) -> Result<Self, NewEncoderError> {
+        let channels = match format.channels.0 {
+            1 => opus::Channels::Mono,
+            2 => opus::Channels::Stereo,
+            _ => { return Err(NewEncoderError::UnsupportedAudioFormat(format)); }
+        };
+
+        // opus only supports a handful of sample rates, and of those only
+        // these give a valid opus frame duration for FRAMES_PER_PACKET
+        // frames per packet:
+        if !matches!(format.sample_rate.0, 12000 | 24000 | 48000) {
+            return Err(NewEncoderError::UnsupportedAudioFormat(format));
+        }
+
         let mut opus = opus::Encoder::new(
-            SAMPLE_RATE.0,
-            opus::Channels::Stereo,
+            format.sample_rate.0,
+            channels,
             opus::Application::Audio,
         )?;
 
@@ -37,7 +50,7 @@ impl Encode for OpusEncoder {
         AudioPacketFormat::OPUS
     }
 
-    fn encode_packet(&mut self, samples: &[Frame], out: &mut [u8]) -> Result<usize, EncodeError> {
-        Ok(self.opus.encode_float(audio::as_interleaved(samples), out)?)
+    fn encode_packet(&mut self, samples: &[Sample], out: &mut [u8]) -> Result<usize, EncodeError> {
+        Ok(self.opus.encode_float(samples, out)?)
     }
 }
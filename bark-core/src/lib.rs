@@ -2,4 +2,5 @@ pub mod audio;
 pub mod consts;
 pub mod decode;
 pub mod encode;
+pub mod parity;
 pub mod receive;
@@ -0,0 +1,281 @@
+//! Forward error correction by XOR parity. After every group of consecutive
+//! audio packets the source sends a parity packet, from which a receiver can
+//! reconstruct any single packet lost from that group.
+
+use bark_protocol::AudioFormat;
+use bark_protocol::packet::{Audio, Parity};
+use bark_protocol::types::{AudioPacketHeader, ParityPacketHeader, SessionId};
+
+const MAX_LENGTH: usize = Audio::HEADER_LENGTH + Audio::MAX_BUFFER_LENGTH;
+
+struct Accumulator {
+    bytes: [u8; MAX_LENGTH],
+    len: usize,
+    length: u16,
+}
+
+impl Accumulator {
+    fn new() -> Self {
+        Accumulator {
+            bytes: [0; MAX_LENGTH],
+            len: 0,
+            length: 0,
+        }
+    }
+
+    fn add(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[0..core::cmp::min(bytes.len(), MAX_LENGTH)];
+
+        for (acc, byte) in self.bytes.iter_mut().zip(bytes) {
+            *acc ^= byte;
+        }
+
+        self.len = core::cmp::max(self.len, bytes.len());
+    }
+
+    fn add_audio(&mut self, audio: &Audio) {
+        let bytes = audio.as_packet().as_bytes();
+        self.add(bytes);
+        self.length ^= bytes.len() as u16;
+    }
+}
+
+pub struct ParityEncoder {
+    group: u16,
+    // session, first seq and format of the packets in the current group
+    sid: SessionId,
+    base_seq: u64,
+    format: AudioFormat,
+    count: u16,
+    acc: Accumulator,
+}
+
+impl ParityEncoder {
+    pub fn new(group: u16) -> Self {
+        ParityEncoder {
+            group,
+            sid: SessionId::new(0),
+            base_seq: 0,
+            format: AudioFormat::DEFAULT,
+            count: 0,
+            acc: Accumulator::new(),
+        }
+    }
+
+    /// Adds an audio packet to the current group, returning a parity packet
+    /// once the group is complete. Receivers take a group to be consecutive
+    /// seqs from one session, so a packet which doesn't follow on from the
+    /// group, as after a failover or after skipping seqs while preempted,
+    /// abandons the group and begins a new one
+    pub fn push(&mut self, audio: &Audio) -> Option<Parity> {
+        let header = audio.header();
+
+        if self.count > 0 && !self.continues_group(header) {
+            log::debug!("audio packet doesn't follow on from parity group, starting a new group: base_seq={}, count={}, seq={}",
+                self.base_seq, self.count, header.seq.get());
+
+            self.count = 0;
+        }
+
+        if self.count == 0 {
+            self.sid = header.sid;
+            self.base_seq = header.seq.get();
+            self.format = header.audio_format();
+            self.acc = Accumulator::new();
+        }
+
+        self.acc.add_audio(audio);
+        self.count += 1;
+
+        if self.count < self.group {
+            return None;
+        }
+
+        self.count = 0;
+
+        let header = ParityPacketHeader {
+            sid: self.sid,
+            base_seq: self.base_seq.into(),
+            count: self.group.into(),
+            length: self.acc.length.into(),
+            _pad: [0; 4],
+        };
+
+        match Parity::new(&header, &self.acc.bytes[0..self.acc.len]) {
+            Ok(parity) => Some(parity),
+            Err(e) => {
+                log::error!("failed to allocate parity packet: {e:?}");
+                None
+            }
+        }
+    }
+
+    fn continues_group(&self, header: &AudioPacketHeader) -> bool {
+        header.sid == self.sid
+            && header.seq.get() == self.base_seq + u64::from(self.count)
+            && header.audio_format() == self.format
+    }
+}
+
+/// Reconstructs the one packet missing from a parity group, given every
+/// other packet in the group
+pub fn recover<'a>(parity: &Parity, others: impl Iterator<Item = &'a Audio>) -> Option<Audio> {
+    let mut acc = Accumulator::new();
+    acc.add(parity.parity_bytes());
+    acc.length = parity.header().length.get();
+
+    for audio in others {
+        acc.add_audio(audio);
+    }
+
+    let length = usize::from(acc.length);
+
+    if length <= Audio::HEADER_LENGTH || length > acc.len {
+        return None;
+    }
+
+    let header: AudioPacketHeader = bytemuck::pod_read_unaligned(&acc.bytes[0..Audio::HEADER_LENGTH]);
+
+    if header.sid != parity.header().sid || !header.audio_format().is_supported() {
+        return None;
+    }
+
+    Audio::new(&header, &acc.bytes[Audio::HEADER_LENGTH..length]).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use bark_protocol::types::{AudioPacketHeader, SessionId};
+    use bytemuck::Zeroable;
+
+    use super::*;
+
+    pub(crate) const SID: SessionId = SessionId::new(1);
+
+    /// Audio packet with a payload that differs from packet to packet, in
+    /// length too
+    pub(crate) fn audio(sid: SessionId, seq: u64) -> Audio {
+        let mut header = AudioPacketHeader {
+            sid,
+            ..AudioPacketHeader::zeroed()
+        };
+
+        header.seq.set(seq);
+
+        let data = (0..32 + seq % 5)
+            .map(|i| (seq * 7 + i) as u8)
+            .collect::<Vec<u8>>();
+
+        Audio::new(&header, &data).unwrap()
+    }
+
+    /// Pushes each packet, returning the parity packets produced
+    pub(crate) fn encode(encoder: &mut ParityEncoder, packets: &[Audio]) -> Vec<Parity> {
+        packets.iter().filter_map(|audio| encoder.push(audio)).collect()
+    }
+
+    fn packets(sid: SessionId, seqs: impl IntoIterator<Item = u64>) -> Vec<Audio> {
+        seqs.into_iter().map(|seq| audio(sid, seq)).collect()
+    }
+
+    #[test]
+    fn parity_after_each_full_group() {
+        let mut encoder = ParityEncoder::new(4);
+        let parity = encode(&mut encoder, &packets(SID, 10..18));
+
+        let groups = parity.iter()
+            .map(|parity| (parity.header().base_seq.get(), parity.header().count.get()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(groups, [(10, 4), (14, 4)]);
+    }
+
+    #[test]
+    fn recovers_any_one_lost_packet() {
+        let mut encoder = ParityEncoder::new(4);
+        let group = packets(SID, 10..14);
+        let parity = encode(&mut encoder, &group).pop().unwrap();
+
+        for lost in 0..group.len() {
+            let others = group.iter().enumerate()
+                .filter(|(idx, _)| *idx != lost)
+                .map(|(_, audio)| audio);
+
+            let recovered = recover(&parity, others).unwrap();
+            assert_eq!(recovered.as_packet().as_bytes(), group[lost].as_packet().as_bytes());
+        }
+    }
+
+    #[test]
+    fn recover_checks_session() {
+        let mut encoder = ParityEncoder::new(2);
+        let group = packets(SID, 10..12);
+        let parity = encode(&mut encoder, &group).pop().unwrap();
+
+        // the other packet from a different session, so what's left over
+        // isn't a packet from the parity packet's session
+        let other = audio(SessionId::new(2), 11);
+        assert!(recover(&parity, [&other].into_iter()).is_none());
+    }
+
+    #[test]
+    fn seq_jump_starts_new_group() {
+        let mut encoder = ParityEncoder::new(4);
+
+        // seqs skipped after 11, as while preempted
+        let mut sent = packets(SID, 10..12);
+        sent.extend(packets(SID, 20..24));
+
+        let parity = encode(&mut encoder, &sent);
+        assert_eq!(parity.len(), 1);
+        assert_eq!(parity[0].header().base_seq.get(), 20);
+
+        let recovered = recover(&parity[0], sent[3..].iter()).unwrap();
+        assert_eq!(recovered.header().seq.get(), 20);
+    }
+
+    #[test]
+    fn seq_going_back_starts_new_group() {
+        let mut encoder = ParityEncoder::new(4);
+
+        // a standby taking over at an earlier seq than ours
+        let mut sent = packets(SID, 10..13);
+        sent.extend(packets(SID, 5..9));
+
+        let parity = encode(&mut encoder, &sent);
+        assert_eq!(parity.len(), 1);
+        assert_eq!(parity[0].header().base_seq.get(), 5);
+    }
+
+    #[test]
+    fn session_change_starts_new_group() {
+        let mut encoder = ParityEncoder::new(4);
+        let other = SessionId::new(2);
+
+        let mut sent = packets(SID, 10..12);
+        sent.extend(packets(other, 12..16));
+
+        let parity = encode(&mut encoder, &sent);
+        assert_eq!(parity.len(), 1);
+        assert_eq!(parity[0].header().sid, other);
+        assert_eq!(parity[0].header().base_seq.get(), 12);
+    }
+
+    #[test]
+    fn format_change_starts_new_group() {
+        let mut encoder = ParityEncoder::new(4);
+
+        let mut sent = packets(SID, 10..16);
+
+        for audio in &mut sent[2..] {
+            audio.header_mut().set_audio_format(AudioFormat {
+                sample_rate: bark_protocol::SampleRate(44100),
+                channels: bark_protocol::ChannelCount(2),
+            });
+        }
+
+        let parity = encode(&mut encoder, &sent);
+        assert_eq!(parity.len(), 1);
+        assert_eq!(parity[0].header().base_seq.get(), 12);
+    }
+}
@@ -0,0 +1,272 @@
+use bark_protocol::time::ClockDelta;
+
+/// Number of clock delta samples the model is fitted to
+const WINDOW: usize = 64;
+
+/// Skew is only estimated once samples span at least this long. Over
+/// shorter spans, noise in the samples swamps any real drift between clocks
+const MIN_SKEW_SPAN_USEC: u64 = 2_000_000;
+
+/// Fewest samples the model must have before it is considered converged,
+/// however well they agree
+const MIN_CONVERGED_SAMPLES: usize = 8;
+
+/// The model is considered converged once the standard error of its
+/// predicted delta falls below this. Well within the offset playback
+/// slews over, so a converged model won't itself cause a slew
+const CONVERGED_ERROR_USEC: f64 = 250.0;
+
+/// Models the difference between the stream's clock and ours as an offset
+/// plus a constant rate of drift, fitted by least squares to recent
+/// samples. Unlike taking the median of recent samples, this predicts the
+/// clock delta at any instant, rather than lagging behind as the clocks
+/// drift apart.
+pub struct ClockModel {
+    samples: [Sample; WINDOW],
+    count: usize,
+    index: usize,
+    fit: Option<Fit>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    // stream clock time the delta was measured at
+    stream_usec: u64,
+    delta_usec: i64,
+}
+
+#[derive(Clone, Copy)]
+struct Fit {
+    // delta predicted at stream clock time `stream_usec`, in microseconds
+    stream_usec: u64,
+    delta_usec: f64,
+    // change in delta per microsecond of stream clock time, or zero if
+    // samples don't yet span long enough to tell
+    skew: f64,
+    has_skew: bool,
+    // standard error of `delta_usec`, from how far samples scatter about
+    // the fitted line
+    error_usec: f64,
+}
+
+impl ClockModel {
+    pub fn new() -> Self {
+        ClockModel {
+            samples: [Sample::default(); WINDOW],
+            count: 0,
+            index: 0,
+            fit: None,
+        }
+    }
+
+    /// Adds a clock delta measured at stream clock time `stream_usec` to
+    /// the model, and refits it
+    pub fn observe(&mut self, stream_usec: u64, delta: ClockDelta) {
+        self.samples[self.index] = Sample { stream_usec, delta_usec: delta.as_micros() };
+
+        if self.count < WINDOW {
+            self.count += 1;
+        }
+
+        self.index += 1;
+        self.index %= WINDOW;
+
+        self.fit = self.refit();
+    }
+
+    /// Predicted clock delta at stream clock time `stream_usec`
+    pub fn predict(&self, stream_usec: u64) -> Option<ClockDelta> {
+        let fit = self.fit?;
+        let elapsed = stream_usec as f64 - fit.stream_usec as f64;
+        let delta = fit.delta_usec + fit.skew * elapsed;
+        Some(ClockDelta::from_micros(delta.round() as i64))
+    }
+
+    /// Estimated rate at which our clock drifts from the stream's, in parts
+    /// per million. Positive when our clock runs fast
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.fit
+            .filter(|fit| fit.has_skew)
+            .map(|fit| fit.skew * 1_000_000.0)
+    }
+
+    /// Whether the model has enough samples, agreeing closely enough, for
+    /// its predictions to be trusted
+    pub fn is_converged(&self) -> bool {
+        self.count >= MIN_CONVERGED_SAMPLES
+            && self.fit.is_some_and(|fit| fit.error_usec < CONVERGED_ERROR_USEC)
+    }
+
+    fn refit(&self) -> Option<Fit> {
+        let samples = &self.samples[0..self.count];
+
+        // fit relative to the most recent sample, keeping values small
+        // enough that nothing is lost to floating point precision
+        let latest = self.samples[(self.index + WINDOW - 1) % WINDOW];
+        let x = |sample: &Sample| sample.stream_usec as f64 - latest.stream_usec as f64;
+        let y = |sample: &Sample| (sample.delta_usec - latest.delta_usec) as f64;
+
+        let n = samples.len() as f64;
+        let mean_x = samples.iter().map(x).sum::<f64>() / n;
+        let mean_y = samples.iter().map(y).sum::<f64>() / n;
+
+        let mut sxx = 0.0;
+        let mut sxy = 0.0;
+        let mut syy = 0.0;
+
+        for sample in samples {
+            let dx = x(sample) - mean_x;
+            let dy = y(sample) - mean_y;
+            sxx += dx * dx;
+            sxy += dx * dy;
+            syy += dy * dy;
+        }
+
+        let earliest = samples.iter().map(|sample| sample.stream_usec).min()?;
+        let span = latest.stream_usec.saturating_sub(earliest);
+        let has_skew = span >= MIN_SKEW_SPAN_USEC && sxx > 0.0;
+
+        let skew = if has_skew { sxy / sxx } else { 0.0 };
+
+        // residual variance about the fitted line, with a degree of freedom
+        // lost for each parameter fitted
+        let params = if has_skew { 2.0 } else { 1.0 };
+        let error_usec = if n > params {
+            let residual = (syy - skew * sxy).max(0.0) / (n - params);
+            (residual / n).sqrt()
+        } else {
+            f64::INFINITY
+        };
+
+        // line passes through the mean of the samples, extrapolate from
+        // there to the latest sample
+        Some(Fit {
+            stream_usec: latest.stream_usec,
+            delta_usec: latest.delta_usec as f64 + mean_y - skew * mean_x,
+            skew,
+            has_skew,
+            error_usec,
+        })
+    }
+}
+
+impl Default for ClockModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::receive::testing;
+
+    use super::*;
+
+    // time packets are sent every 200ms
+    const INTERVAL_USEC: u64 = 200_000;
+    const START_USEC: u64 = 1_000_000_000;
+
+    /// Feeds `count` samples of a clock delta starting at `offset_usec` and
+    /// drifting at `drift_ppm`, each off by up to `noise_usec`
+    fn observe(model: &mut ClockModel, count: u64, offset_usec: f64, drift_ppm: f64, noise_usec: f64) {
+        let mut noise = testing::noise(noise_usec);
+
+        for i in 0..count {
+            let elapsed = i * INTERVAL_USEC;
+            let delta = expected(offset_usec, drift_ppm, elapsed) + noise();
+            model.observe(START_USEC + elapsed, ClockDelta::from_micros(delta.round() as i64));
+        }
+    }
+
+    fn expected(offset_usec: f64, drift_ppm: f64, elapsed_usec: u64) -> f64 {
+        offset_usec + drift_ppm * elapsed_usec as f64 / 1_000_000.0
+    }
+
+    fn predict(model: &ClockModel, elapsed_usec: u64) -> f64 {
+        model.predict(START_USEC + elapsed_usec).expect("prediction").as_micros() as f64
+    }
+
+    #[test]
+    fn predicts_nothing_without_samples() {
+        let model = ClockModel::new();
+        assert!(model.predict(START_USEC).is_none());
+        assert!(model.drift_ppm().is_none());
+        assert!(!model.is_converged());
+    }
+
+    #[test]
+    fn no_drift_before_min_span() {
+        let mut model = ClockModel::new();
+
+        // 10 samples span 1.8s, short of the 2s needed
+        observe(&mut model, 10, 1000.0, 50.0, 0.0);
+        assert!(model.drift_ppm().is_none());
+
+        // one more reaches it
+        observe(&mut model, 11, 1000.0, 50.0, 0.0);
+        assert!(model.drift_ppm().is_some());
+    }
+
+    #[test]
+    fn tracks_offset_and_drift() {
+        let mut model = ClockModel::new();
+        observe(&mut model, 64, 1000.0, 50.0, 0.0);
+
+        // positive when our clock runs fast, ie. the delta grows
+        let drift = model.drift_ppm().expect("drift");
+        assert!((drift - 50.0).abs() < 0.1, "drift {drift}ppm");
+
+        // extrapolates a second past the last sample
+        let elapsed = 64 * INTERVAL_USEC + 1_000_000;
+        let error = predict(&model, elapsed) - expected(1000.0, 50.0, elapsed);
+        assert!(error.abs() <= 2.0, "prediction off by {error}us");
+    }
+
+    #[test]
+    fn tracks_negative_drift_through_noise() {
+        let mut model = ClockModel::new();
+        observe(&mut model, 64, -5000.0, -30.0, 100.0);
+
+        let drift = model.drift_ppm().expect("drift");
+        assert!((drift + 30.0).abs() < 10.0, "drift {drift}ppm");
+
+        let elapsed = 63 * INTERVAL_USEC;
+        let error = predict(&model, elapsed) - expected(-5000.0, -30.0, elapsed);
+        assert!(error.abs() < 100.0, "prediction off by {error}us");
+    }
+
+    #[test]
+    fn converges_after_min_samples() {
+        let mut model = ClockModel::new();
+
+        observe(&mut model, MIN_CONVERGED_SAMPLES as u64 - 1, 1000.0, 0.0, 0.0);
+        assert!(!model.is_converged());
+
+        model.observe(START_USEC + 10 * INTERVAL_USEC, ClockDelta::from_micros(1000));
+        assert!(model.is_converged());
+    }
+
+    #[test]
+    fn converges_with_drift() {
+        // residuals are measured about the fitted line, so steady drift
+        // alone doesn't hold off convergence
+        let mut model = ClockModel::new();
+        observe(&mut model, 32, 1000.0, 200.0, 0.0);
+        assert!(model.drift_ppm().is_some());
+        assert!(model.is_converged());
+    }
+
+    #[test]
+    fn noise_holds_off_convergence() {
+        let mut model = ClockModel::new();
+
+        // standard error of about 1ms with this many samples
+        observe(&mut model, MIN_CONVERGED_SAMPLES as u64, 1000.0, 0.0, 5000.0);
+        assert!(!model.is_converged());
+
+        // while modest noise averages out within the window
+        let mut model = ClockModel::new();
+        observe(&mut model, 64, 1000.0, 0.0, 500.0);
+        assert!(model.is_converged());
+    }
+}
@@ -0,0 +1,166 @@
+use bark_protocol::ChannelCount;
+
+use crate::audio::Sample;
+
+/// Gain stage applied to output audio, able to ramp smoothly between levels
+/// so that changes don't click
+pub struct Gain {
+    gain: f32,
+    target: f32,
+    // change in gain per frame while ramping towards target
+    step: f32,
+}
+
+impl Gain {
+    pub fn new(gain: f32) -> Self {
+        Gain { gain, target: gain, step: 0.0 }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// Ramps linearly to `target` over the next `frames` frames of audio
+    pub fn ramp_to(&mut self, target: f32, frames: usize) {
+        self.target = target;
+
+        if frames == 0 {
+            self.gain = target;
+            self.step = 0.0;
+        } else {
+            self.step = (target - self.gain) / frames as f32;
+        }
+    }
+
+    /// Whether output is currently silenced entirely
+    pub fn is_silent(&self) -> bool {
+        self.gain == 0.0 && self.target == 0.0
+    }
+
+    /// Applies gain to a buffer of interleaved audio, advancing any ramp in
+    /// progress
+    pub fn process(&mut self, buffer: &mut [Sample], channels: ChannelCount) {
+        if self.gain == self.target && self.gain == 1.0 {
+            return;
+        }
+
+        for frame in buffer.chunks_exact_mut(usize::from(channels)) {
+            if self.gain != self.target {
+                let next = self.gain + self.step;
+
+                // stop exactly on target rather than overshooting it
+                let reached = if self.step > 0.0 { next >= self.target } else { next <= self.target };
+                self.gain = if reached { self.target } else { next };
+            }
+
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+/// Converts a volume in percent to a linear gain. Volume follows a cubic
+/// curve, which is much closer to how loudness is perceived than a linear
+/// one, so that each step of the volume sounds about as big as the last
+pub fn volume_gain(percent: u8) -> f32 {
+    let volume = f32::from(percent.min(100)) / 100.0;
+    volume * volume * volume
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEREO: ChannelCount = ChannelCount(2);
+
+    /// Runs `frames` frames of full scale stereo audio through `gain`,
+    /// returning the gain applied to each frame
+    fn process(gain: &mut Gain, frames: usize) -> Vec<f32> {
+        let mut buffer = vec![1.0; frames * 2];
+        gain.process(&mut buffer, STEREO);
+
+        buffer.chunks_exact(2)
+            .map(|frame| {
+                assert_eq!(frame[0], frame[1]);
+                frame[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unity_gain_leaves_audio_alone() {
+        let mut gain = Gain::new(1.0);
+        assert_eq!(process(&mut gain, 3), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn fade_out_reaches_zero_and_stays_there() {
+        let mut gain = Gain::new(1.0);
+        gain.ramp_to(0.0, 4);
+        assert!(!gain.is_silent());
+
+        assert_eq!(process(&mut gain, 6), [0.75, 0.5, 0.25, 0.0, 0.0, 0.0]);
+        assert_eq!(gain.gain(), 0.0);
+        assert!(gain.is_silent());
+    }
+
+    #[test]
+    fn fade_in_from_held_silence() {
+        let mut gain = Gain::new(1.0);
+
+        // cut straight to silence, as when a stream has paused
+        gain.ramp_to(0.0, 0);
+        assert!(gain.is_silent());
+        assert_eq!(process(&mut gain, 2), [0.0, 0.0]);
+
+        gain.ramp_to(1.0, 4);
+        assert!(!gain.is_silent());
+        assert_eq!(process(&mut gain, 6), [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert_eq!(gain.gain(), 1.0);
+    }
+
+    #[test]
+    fn ramp_changes_course_from_where_it_is() {
+        let mut gain = Gain::new(1.0);
+        gain.ramp_to(0.0, 4);
+        assert_eq!(process(&mut gain, 2), [0.75, 0.5]);
+
+        gain.ramp_to(1.0, 2);
+        assert_eq!(process(&mut gain, 3), [0.75, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn ramps_to_volume_target_exactly() {
+        let mut gain = Gain::new(1.0);
+        let target = volume_gain(50);
+
+        gain.ramp_to(target, 3);
+        assert_eq!(gain.target(), target);
+
+        // steps don't add up to the target exactly, but never pass it and
+        // land on it once within rounding
+        let applied = process(&mut gain, 5);
+        assert!(applied.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(applied.iter().all(|gain| *gain >= target));
+        assert!((applied[2] - target).abs() < 1e-6);
+        assert_eq!(applied[4], target);
+        assert_eq!(gain.gain(), target);
+    }
+
+    #[test]
+    fn volume_follows_cubic_curve() {
+        assert_eq!(volume_gain(0), 0.0);
+        assert_eq!(volume_gain(50), 0.125);
+        assert_eq!(volume_gain(100), 1.0);
+
+        // out of range volumes are treated as full volume
+        assert_eq!(volume_gain(150), 1.0);
+
+        assert!((1..=100).all(|percent| volume_gain(percent) > volume_gain(percent - 1)));
+    }
+}
@@ -1,4 +1,23 @@
+pub mod clock;
+pub mod gain;
 pub mod pipeline;
 pub mod queue;
 pub mod resample;
+pub mod rtt;
 pub mod timing;
+
+#[cfg(test)]
+pub(crate) mod testing {
+    /// Deterministic pseudo random noise, so tests are repeatable. Each call
+    /// returns a value between -`amplitude` and `amplitude`
+    pub(crate) fn noise(amplitude: f64) -> impl FnMut() -> f64 {
+        let mut seed = 0x2545f4914f6cdd1du64;
+
+        move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            ((seed % 2001) as f64 / 1000.0 - 1.0) * amplitude
+        }
+    }
+}
@@ -1,26 +1,43 @@
-use bark_protocol::FRAMES_PER_PACKET;
-use bytemuck::Zeroable;
+use bark_protocol::{AudioFormat, ChannelCount, FRAMES_PER_PACKET};
 
 use bark_protocol::packet::Audio;
 use bark_protocol::types::AudioPacketHeader;
 
-use crate::audio::Frame;
+use crate::audio::{self, PacketSamples, Sample};
+use crate::consts::MAX_RESAMPLE_RATIO;
 use crate::decode::Decoder;
 use crate::receive::resample::Resampler;
-use crate::receive::timing::{RateAdjust, Timing};
+use crate::receive::timing::{RateAdjust, RateParams, Timing};
 
 pub struct Pipeline {
     /// None indicates error creating decoder, we cannot decode this stream
     decoder: Option<Decoder>,
+    stream: AudioFormat,
+    output: AudioFormat,
     resampler: Resampler,
     rate_adjust: RateAdjust,
+    resync: Option<Resync>,
+}
+
+/// A jump in playback in progress, when the stream got too far off to slew
+enum Resync {
+    /// Behind the stream, discarding `discard` more packets to catch up.
+    /// `from` holds the audio that would have played next, which is
+    /// crossfaded into the audio after the jump
+    Skip { discard: usize, from: Option<Box<PacketSamples>> },
+    /// Ahead of the stream, playing `packets` more packets of silence for
+    /// it to catch up with us
+    Insert { packets: usize },
+    /// Finished inserting silence, fade the stream back in
+    FadeIn,
 }
 
 impl Pipeline {
-    pub fn new(header: &AudioPacketHeader) -> Self {
-        let decoder = match Decoder::new(header) {
+    pub fn new(header: &AudioPacketHeader, output: AudioFormat, rate: RateParams) -> Self {
+        let decoder = match new_decoder(header, output) {
             Ok(dec) => {
-                log::info!("instantiated decoder for new stream: {}", dec.describe());
+                log::info!("instantiated decoder for new stream: {} ({})",
+                    dec.describe(), dec.format());
                 Some(dec)
             }
             Err(err) => {
@@ -29,10 +46,19 @@ impl Pipeline {
             }
         };
 
+        // if we can't decode the stream we'll only be outputting silence,
+        // so it doesn't matter what we think its format is
+        let stream = decoder.as_ref()
+            .map(|dec| dec.format())
+            .unwrap_or(output);
+
         Pipeline {
             decoder,
-            resampler: Resampler::new(),
-            rate_adjust: RateAdjust::new(),
+            stream,
+            output,
+            resampler: Resampler::new(stream.sample_rate, output),
+            rate_adjust: RateAdjust::new(stream.sample_rate, rate),
+            resync: None,
         }
     }
 
@@ -40,31 +66,168 @@ impl Pipeline {
         self.rate_adjust.slew()
     }
 
-    pub fn set_timing(&mut self, timing: Timing) {
+    /// Adjusts playback towards `timing`. Returns true if we were too far
+    /// off to slew, and playback is jumping to the stream instead
+    pub fn set_timing(&mut self, timing: Timing) -> bool {
+        if self.resync.is_some() {
+            // timing is meaningless until the jump is done
+            return false;
+        }
+
         let rate = self.rate_adjust.sample_rate(timing);
-        let _ = self.resampler.set_input_rate(rate.0);
+        let _ = self.resampler.set_input_rate(rate);
+
+        let Some(offset) = self.rate_adjust.take_resync() else {
+            return false;
+        };
+
+        let packet_secs = FRAMES_PER_PACKET as f64 / f64::from(self.stream.sample_rate.0);
+        let packets = ((offset.abs() / packet_secs).round() as usize).max(1);
+
+        self.resync = Some(if offset > 0.0 {
+            Resync::Skip { discard: packets - 1, from: None }
+        } else {
+            Resync::Insert { packets }
+        });
+
+        true
+    }
+
+    /// Writes a packet's worth of silence to `out` while playback is jumping
+    /// ahead of the stream, in place of a packet from it. Returns None once
+    /// the stream should carry on playing
+    pub fn insert_silence(&mut self, out: &mut [Sample]) -> Option<usize> {
+        let Some(Resync::Insert { packets }) = &mut self.resync else {
+            return None;
+        };
+
+        *packets -= 1;
+
+        if *packets == 0 {
+            self.resync = Some(Resync::FadeIn);
+        }
+
+        // run silence through the resampler rather than writing it straight
+        // out, so that it stays primed
+        let silence: PacketSamples = [0.0; bark_protocol::MAX_SAMPLES_PER_PACKET];
+        let silence = &silence[0..self.output.samples_per_packet()];
+
+        let resample = self.resampler.process(silence, out)
+            .expect("resample error!");
+
+        Some(resample.output_written.0)
     }
 
-    pub fn process(&mut self, packet: Option<&Audio>, out: &mut [Frame]) -> usize {
+    /// Decodes and resamples a single packet of audio into `out`, returning
+    /// the number of frames written. Output audio has the channel count of
+    /// the output format this pipeline was created with.
+    pub fn process(&mut self, packet: Option<&Audio>, out: &mut [Sample]) -> usize {
         // decode packet
-        let mut decode_buffer = [Frame::zeroed(); FRAMES_PER_PACKET];
+        let mut decode_buffer: PacketSamples = [0.0; bark_protocol::MAX_SAMPLES_PER_PACKET];
+        let decoded = &mut decode_buffer[0..self.stream.samples_per_packet()];
 
         if let Some(decoder) = self.decoder.as_mut() {
-            match decoder.decode(packet, &mut decode_buffer) {
+            match decoder.decode(packet, decoded) {
                 Ok(()) => {}
                 Err(e) => {
                     log::warn!("error in decoder, skipping packet: {e}");
-                    decode_buffer.fill(Frame::zeroed());
+                    decoded.fill(0.0);
                 }
             }
         }
 
+        // map stream channels to output channels
+        let mut remap_buffer: PacketSamples = [0.0; bark_protocol::MAX_SAMPLES_PER_PACKET];
+        let remapped = &mut remap_buffer[0..self.output.samples_per_packet()];
+        audio::remap_channels(decoded, self.stream.channels, remapped, self.output.channels);
+
+        match self.resync.take() {
+            None => {}
+            Some(Resync::Skip { discard, from: None }) => {
+                let mut from = Box::new([0.0; bark_protocol::MAX_SAMPLES_PER_PACKET]);
+                from[0..remapped.len()].copy_from_slice(remapped);
+                self.resync = Some(Resync::Skip { discard, from: Some(from) });
+                return 0;
+            }
+            Some(Resync::Skip { discard, from }) if discard > 0 => {
+                self.resync = Some(Resync::Skip { discard: discard - 1, from });
+                return 0;
+            }
+            Some(Resync::Skip { from: Some(from), .. }) => {
+                crossfade(&from[0..remapped.len()], remapped, self.output.channels);
+            }
+            Some(Resync::Insert { packets }) => {
+                // fade out into the silence about to be inserted
+                fade(remapped, self.output.channels, 1.0, 0.0);
+                self.resync = Some(Resync::Insert { packets });
+            }
+            Some(Resync::FadeIn) => {
+                fade(remapped, self.output.channels, 0.0, 1.0);
+            }
+        }
+
         // resample decoded audio
-        let resample = self.resampler.process(&decode_buffer, out)
+        let resample = self.resampler.process(remapped, out)
             .expect("resample error!");
 
-        assert_eq!(resample.input_read.0, decode_buffer.len());
+        assert_eq!(resample.input_read.0, FRAMES_PER_PACKET);
 
         resample.output_written.0
     }
 }
+
+/// Crossfades linearly from `from` into `to` over the length of the buffer,
+/// leaving the result in `to`
+fn crossfade(from: &[Sample], to: &mut [Sample], channels: ChannelCount) {
+    let channels = usize::from(channels);
+    let frames = to.len() / channels;
+
+    for (index, (to, from)) in to.chunks_exact_mut(channels).zip(from.chunks_exact(channels)).enumerate() {
+        let gain = index as f32 / frames as f32;
+
+        for (to, from) in to.iter_mut().zip(from) {
+            *to = *from * (1.0 - gain) + *to * gain;
+        }
+    }
+}
+
+/// Ramps gain linearly from `start` to `end` over the length of the buffer
+fn fade(buffer: &mut [Sample], channels: ChannelCount, start: f32, end: f32) {
+    let channels = usize::from(channels);
+    let frames = buffer.len() / channels;
+
+    for (index, frame) in buffer.chunks_exact_mut(channels).enumerate() {
+        let gain = start + (end - start) * index as f32 / frames as f32;
+
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+fn new_decoder(header: &AudioPacketHeader, output: AudioFormat)
+    -> Result<Decoder, NewPipelineError>
+{
+    let decoder = Decoder::new(header)?;
+
+    let stream_rate = u64::from(decoder.format().sample_rate);
+    let output_rate = u64::from(output.sample_rate);
+
+    // we only have so much room to buffer resampled audio
+    if output_rate > stream_rate * MAX_RESAMPLE_RATIO as u64 {
+        return Err(NewPipelineError::ResampleRatio {
+            stream: decoder.format(),
+            output,
+        });
+    }
+
+    Ok(decoder)
+}
+
+#[derive(Debug, thiserror::Error)]
+enum NewPipelineError {
+    #[error(transparent)]
+    Decoder(#[from] crate::decode::NewDecoderError),
+    #[error("cannot resample stream ({stream}) to output ({output})")]
+    ResampleRatio { stream: AudioFormat, output: AudioFormat },
+}
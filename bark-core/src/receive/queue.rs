@@ -1,15 +1,20 @@
 use core::num::NonZeroU16;
+use core::time::Duration;
 
-use heapless::Deque;
+use heapless::{Deque, Vec};
 
-use bark_protocol::packet::Audio;
-use bark_protocol::types::AudioPacketHeader;
-use bark_protocol::time::{SampleDuration, Timestamp};
+use bark_protocol::packet::{Audio, Parity, Retransmit};
+use bark_protocol::types::{AudioPacketHeader, RetransmitPacket, SessionId, TimestampMicros};
+use bark_protocol::time::Timestamp;
 
-use crate::consts::MAX_QUEUED_DECODE_SEGMENTS;
+use crate::consts::{MAX_QUEUED_DECODE_SEGMENTS, MAX_QUEUED_PARITY};
+use crate::parity;
 
 pub struct PacketQueue {
     queue: Deque<Option<Audio>, MAX_QUEUED_DECODE_SEGMENTS>,
+    /// Parity packets for groups which may still be needed to reconstruct
+    /// a lost packet
+    parity: Vec<Parity, MAX_QUEUED_PARITY>,
     /// The seq of the first packet in the queue, the rest are implied
     head_seq: u64,
     /// We delay yielding packets when a queue is first started (or reset), to
@@ -27,7 +32,8 @@ impl PacketQueue {
     pub fn new(initial: &AudioPacketHeader) -> Self {
         PacketQueue {
             queue: Deque::new(),
-            head_seq: initial.seq,
+            parity: Vec::new(),
+            head_seq: initial.seq.get(),
             start: DelayStart::init(initial),
         }
     }
@@ -42,13 +48,14 @@ impl PacketQueue {
     }
 
     pub fn insert_packet(&mut self, packet: Audio) {
-        let packet_seq = packet.header().seq;
+        let packet_seq = packet.header().seq.get();
         let head_seq = self.head_seq;
         let tail_seq = self.head_seq + self.queue.capacity() as u64;
 
         match self.queue_slot_mut(packet_seq) {
             Ok(slot@&mut None) => {
                 *slot = Some(packet);
+                self.recover_lost();
             }
             Ok(Some(_)) => {
                 log::warn!("received duplicate packet, retaining first received: packet_seq={packet_seq}");
@@ -63,12 +70,81 @@ impl PacketQueue {
                 self.head_seq = packet_seq;
                 self.start = DelayStart::init(packet.header());
                 self.queue.clear();
+                self.parity.clear();
                 self.queue.push_back(Some(packet)).expect("always room in queue after clear");
 
             }
         }
     }
 
+    pub fn insert_parity(&mut self, parity: Parity) {
+        if self.parity.is_full() {
+            // oldest parity packet is the least likely to still be useful
+            self.parity.remove(0);
+        }
+
+        let Ok(()) = self.parity.push(parity) else {
+            unreachable!("made room above");
+        };
+
+        self.recover_lost();
+    }
+
+    /// Reconstructs any packet which is the only one missing from a group
+    /// we hold a parity packet for
+    fn recover_lost(&mut self) {
+        // groups which have begun playing can no longer be used to recover,
+        // we don't have their earlier packets anymore
+        let head_seq = self.head_seq;
+        self.parity.retain(|parity| parity.header().base_seq.get() >= head_seq);
+
+        let mut idx = 0;
+
+        while idx < self.parity.len() {
+            let header = *self.parity[idx].header();
+            let base_seq = header.base_seq.get();
+            let group = base_seq..(base_seq + u64::from(header.count.get()));
+
+            let mut missing = group.clone().filter(|seq| self.queue_slot(*seq).is_none());
+
+            let (Some(lost_seq), None) = (missing.next(), missing.next()) else {
+                // either nothing missing, in which case this parity packet
+                // has served its purpose, or too much missing to recover
+                if group.clone().all(|seq| self.queue_slot(seq).is_some()) {
+                    self.parity.swap_remove(idx);
+                } else {
+                    idx += 1;
+                }
+                continue;
+            };
+
+            let parity = self.parity.swap_remove(idx);
+
+            let others = group.filter(|seq| *seq != lost_seq)
+                .filter_map(|seq| self.queue_slot(seq));
+
+            let Some(packet) = parity::recover(&parity, others) else {
+                log::warn!("failed to recover lost packet from parity: seq={lost_seq}");
+                continue;
+            };
+
+            if packet.header().seq.get() != lost_seq {
+                log::warn!("recovered packet from parity with unexpected seq, dropping: expected={lost_seq}, seq={}", packet.header().seq.get());
+                continue;
+            }
+
+            if let Ok(slot) = self.queue_slot_mut(lost_seq) {
+                log::debug!("recovered lost packet from parity: seq={lost_seq}");
+                *slot = Some(packet);
+            }
+        }
+    }
+
+    fn queue_slot(&self, seq: u64) -> Option<&Audio> {
+        let idx = seq.checked_sub(self.head_seq)? as usize;
+        self.queue.iter().nth(idx)?.as_ref()
+    }
+
     fn queue_slot_mut(&mut self, seq: u64) -> Result<&mut Option<Audio>, NoSlot> {
         let idx = seq.checked_sub(self.head_seq).ok_or(NoSlot::InPast)? as usize;
 
@@ -95,6 +171,114 @@ impl PacketQueue {
     pub fn len(&self) -> usize {
         self.queue.len()
     }
+
+    /// The packet that will be yielded next by `pop_front`, if it has
+    /// arrived
+    pub fn front(&self) -> Option<&Audio> {
+        self.queue_slot(self.head_seq)
+    }
+
+    /// The seq of the packet that will be yielded next by `pop_front`
+    pub fn head_seq(&self) -> u64 {
+        self.head_seq
+    }
+
+    /// Seqs of packets which have not been received yet, but which a later
+    /// packet has. These are gaps in the stream, most likely due to packet
+    /// loss, and could still be filled in if they arrive before playback
+    /// reaches them
+    pub fn missing(&self) -> impl Iterator<Item = u64> + '_ {
+        self.queue.iter()
+            .zip(self.head_seq..)
+            .filter(|(slot, _)| slot.is_none())
+            .map(|(_, seq)| seq)
+    }
+}
+
+// one retransmit request can always cover every packet the queue holds
+const _: () = assert!(MAX_QUEUED_DECODE_SEGMENTS as u64 <= RetransmitPacket::MAX_SPAN);
+
+/// Tracks which packets missing from a [`PacketQueue`] we have asked the
+/// stream source to retransmit, and when
+pub struct RetransmitRequests {
+    // indexed by seq modulo the queue capacity, which is different for
+    // every seq the queue can hold at once
+    requested: [Option<Requested>; MAX_QUEUED_DECODE_SEGMENTS],
+}
+
+#[derive(Clone, Copy)]
+struct Requested {
+    seq: u64,
+    at: TimestampMicros,
+}
+
+impl RetransmitRequests {
+    pub fn new() -> Self {
+        RetransmitRequests {
+            requested: [None; MAX_QUEUED_DECODE_SEGMENTS],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.requested = [None; MAX_QUEUED_DECODE_SEGMENTS];
+    }
+
+    /// Builds a request for the source to retransmit packets missing from
+    /// `queue`, if any of them could still arrive in time to play. A
+    /// retransmitted packet takes `round_trip` to arrive, so packets due to
+    /// play sooner than that aren't asked for, and nor are packets asked
+    /// for less than `round_trip` ago
+    pub fn request(
+        &mut self,
+        queue: &PacketQueue,
+        sid: SessionId,
+        packet_duration: Duration,
+        round_trip: Duration,
+        now: TimestampMicros,
+    ) -> Option<Retransmit> {
+        let head_seq = queue.head_seq();
+        let mut request: Option<Retransmit> = None;
+
+        for seq in queue.missing() {
+            let until_play = packet_duration * u32::try_from(seq - head_seq).unwrap();
+
+            if until_play < round_trip {
+                // too late, a retransmit would arrive after we need it
+                continue;
+            }
+
+            let slot = &mut self.requested[(seq % MAX_QUEUED_DECODE_SEGMENTS as u64) as usize];
+
+            if let Some(requested) = slot.filter(|requested| requested.seq == seq) {
+                let since = Duration::from_micros(now.get().saturating_sub(requested.at.get()));
+
+                if since < round_trip {
+                    // still waiting on a previous request
+                    continue;
+                }
+            }
+
+            let request = request.get_or_insert_with(|| {
+                Retransmit::new(sid, seq).expect("allocate Retransmit packet")
+            });
+
+            if !request.data_mut().request(seq) {
+                // remaining seqs don't fit in this request, the next
+                // call will pick them up
+                break;
+            }
+
+            *slot = Some(Requested { seq, at: now });
+        }
+
+        request
+    }
+}
+
+impl Default for RetransmitRequests {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 enum DelayStart {
@@ -111,7 +295,8 @@ impl DelayStart {
         let delay = initial_pts.saturating_duration_since(initial_dts);
 
         // calculate number of packets this delay represents:
-        let packet_delay = delay.to_frame_count() / SampleDuration::ONE_PACKET.to_frame_count();
+        let packet_duration = header.audio_format().packet_duration();
+        let packet_delay = delay.to_frame_count() / packet_duration.to_frame_count();
 
         // quick n dirty round up:
         let packet_delay = packet_delay + 1;
@@ -135,3 +320,205 @@ impl DelayStart {
         matches!(self, DelayStart::Live)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::parity::ParityEncoder;
+    use crate::parity::tests::{audio, encode, SID};
+
+    use super::*;
+
+    /// Queue starting at seq 0, and a group of packets from it with their
+    /// parity packet
+    fn setup(group: u16) -> (PacketQueue, Vec<Audio>, Parity) {
+        let packets = (0..u64::from(group)).map(|seq| audio(SID, seq)).collect::<Vec<_>>();
+        let parity = encode(&mut ParityEncoder::new(group), &packets).pop().unwrap();
+        let queue = PacketQueue::new(packets[0].header());
+        (queue, packets, parity)
+    }
+
+    fn bytes(audio: Option<&Audio>) -> Option<&[u8]> {
+        audio.map(|audio| audio.as_packet().as_bytes())
+    }
+
+    #[test]
+    fn recovers_lost_packet_when_parity_arrives() {
+        let (mut queue, packets, parity) = setup(4);
+
+        for seq in [0, 1, 3] {
+            queue.insert_packet(audio(SID, seq));
+        }
+
+        assert!(queue.queue_slot(2).is_none());
+
+        queue.insert_parity(parity);
+        assert_eq!(bytes(queue.queue_slot(2)), bytes(Some(&packets[2])));
+        assert!(queue.parity.is_empty());
+    }
+
+    #[test]
+    fn recovers_lost_packet_when_parity_arrives_first() {
+        let (mut queue, packets, parity) = setup(4);
+
+        queue.insert_parity(parity);
+
+        for seq in [1, 2, 3] {
+            queue.insert_packet(audio(SID, seq));
+        }
+
+        assert_eq!(bytes(queue.queue_slot(0)), bytes(Some(&packets[0])));
+    }
+
+    #[test]
+    fn holds_parity_until_only_one_packet_missing() {
+        let (mut queue, packets, parity) = setup(4);
+
+        queue.insert_packet(audio(SID, 0));
+        queue.insert_packet(audio(SID, 3));
+        queue.insert_parity(parity);
+
+        // two missing, can't recover either yet
+        assert!(queue.queue_slot(1).is_none());
+        assert!(queue.queue_slot(2).is_none());
+        assert_eq!(queue.parity.len(), 1);
+
+        queue.insert_packet(audio(SID, 1));
+        assert_eq!(bytes(queue.queue_slot(2)), bytes(Some(&packets[2])));
+        assert!(queue.parity.is_empty());
+    }
+
+    #[test]
+    fn drops_parity_once_group_complete() {
+        let (mut queue, _, parity) = setup(4);
+
+        for seq in 0..4 {
+            queue.insert_packet(audio(SID, seq));
+        }
+
+        queue.insert_parity(parity);
+        assert!(queue.parity.is_empty());
+    }
+
+    #[test]
+    fn drops_parity_for_group_already_playing() {
+        let (mut queue, _, parity) = setup(4);
+
+        for seq in [0, 1, 3] {
+            queue.insert_packet(audio(SID, seq));
+        }
+
+        // play out the start delay and the first packet of the group
+        while queue.head_seq() == 0 {
+            queue.pop_front();
+        }
+
+        queue.insert_parity(parity);
+        assert!(queue.queue_slot(2).is_none());
+        assert!(queue.parity.is_empty());
+    }
+
+    const PACKET_DURATION: Duration = Duration::from_micros(2500);
+
+    fn queue_with(seqs: impl IntoIterator<Item = u64>) -> PacketQueue {
+        let mut queue = PacketQueue::new(audio(SID, 0).header());
+
+        for seq in seqs {
+            queue.insert_packet(audio(SID, seq));
+        }
+
+        queue
+    }
+
+    /// Requested seqs, with round trip and time in milliseconds
+    fn request(requests: &mut RetransmitRequests, queue: &PacketQueue, round_trip_ms: u64, now_ms: u64) -> Option<Vec<u64>> {
+        let round_trip = Duration::from_millis(round_trip_ms);
+        let now = TimestampMicros::new(now_ms * 1000);
+
+        let request = requests.request(queue, SID, PACKET_DURATION, round_trip, now)?;
+        assert_eq!(request.data().sid, SID);
+        Some(request.data().seqs().collect())
+    }
+
+    #[test]
+    fn missing_lists_gaps() {
+        let queue = queue_with([0, 3, 4, 7]);
+        assert_eq!(queue.missing().collect::<Vec<_>>(), [1, 2, 5, 6]);
+    }
+
+    #[test]
+    fn no_request_when_nothing_missing() {
+        let mut requests = RetransmitRequests::new();
+
+        assert_eq!(request(&mut requests, &queue_with([]), 1, 0), None);
+        assert_eq!(request(&mut requests, &queue_with(0..4), 1, 0), None);
+    }
+
+    #[test]
+    fn requests_every_gap() {
+        let mut requests = RetransmitRequests::new();
+        let queue = queue_with([0, 3, 4, 7]);
+
+        let request = requests.request(&queue, SID, PACKET_DURATION, Duration::from_millis(1), TimestampMicros::new(0)).unwrap();
+        assert_eq!(request.data().base_seq.get(), 1);
+        assert_eq!(request.data().missing.get(), 0b110011);
+    }
+
+    #[test]
+    fn skips_packets_due_before_retransmit_could_arrive() {
+        let mut requests = RetransmitRequests::new();
+        let queue = queue_with([0, 3, 4, 7]);
+
+        // 1 and 2 play within 6ms, base seq moves up to the first in time
+        let request = requests.request(&queue, SID, PACKET_DURATION, Duration::from_millis(6), TimestampMicros::new(0)).unwrap();
+        assert_eq!(request.data().base_seq.get(), 5);
+        assert_eq!(request.data().seqs().collect::<Vec<_>>(), [5, 6]);
+    }
+
+    #[test]
+    fn asks_again_only_after_a_round_trip() {
+        let mut requests = RetransmitRequests::new();
+        let mut queue = queue_with([0, 3]);
+
+        assert_eq!(request(&mut requests, &queue, 1, 100), Some(vec![1, 2]));
+        assert_eq!(request(&mut requests, &queue, 1, 100), None);
+
+        // a newly missing packet is asked for right away, without the ones
+        // still awaiting a reply
+        queue.insert_packet(audio(SID, 5));
+        assert_eq!(request(&mut requests, &queue, 1, 100), Some(vec![4]));
+
+        assert_eq!(request(&mut requests, &queue, 1, 101), Some(vec![1, 2, 4]));
+    }
+
+    #[test]
+    fn largest_gap_fits_one_request() {
+        let mut requests = RetransmitRequests::new();
+        let last = MAX_QUEUED_DECODE_SEGMENTS as u64 - 1;
+        let queue = queue_with([0, last]);
+
+        let seqs = request(&mut requests, &queue, 1, 0).unwrap();
+        assert_eq!(seqs, (1..last).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tracks_seqs_a_whole_queue_apart_separately() {
+        let mut requests = RetransmitRequests::new();
+        let mut queue = queue_with([0, 1, 2, 3, 4, 6]);
+
+        assert_eq!(request(&mut requests, &queue, 1, 0), Some(vec![5]));
+
+        // play on until 5 is long gone, then lose the seq a whole queue
+        // length after it, which is tracked in the same place
+        while queue.head_seq() < 10 {
+            queue.pop_front();
+        }
+
+        let reused = 5 + MAX_QUEUED_DECODE_SEGMENTS as u64;
+        queue.insert_packet(audio(SID, reused + 1));
+
+        let seqs = request(&mut requests, &queue, 1, 0).unwrap();
+        assert!(seqs.contains(&reused));
+    }
+}
@@ -1,10 +1,21 @@
+use bark_protocol::{AudioFormat, ChannelCount, SampleRate, FRAMES_PER_PACKET};
+use heapless::Vec;
 use soxr::Soxr;
 use soxr::format::Stereo;
 
-use crate::audio::{Frame, FrameCount, Sample};
+use crate::audio::{FrameCount, Sample};
+use crate::consts::MAX_OUTPUT_FRAMES_PER_PACKET;
+
+// soxr formats are fixed at compile time, so to support any channel count
+// we run one stereo resampler per pair of channels. every resampler is
+// configured identically, so they always produce the same amount of output.
+type StereoFrame = [Sample; 2];
+const MAX_CHANNEL_PAIRS: usize = (bark_protocol::MAX_CHANNELS.0 as usize).div_ceil(2);
 
 pub struct Resampler {
-    soxr: Soxr<Stereo<Sample>>,
+    channels: ChannelCount,
+    output_rate: SampleRate,
+    pairs: Vec<Soxr<Stereo<Sample>>, MAX_CHANNEL_PAIRS>,
 }
 
 pub struct ProcessResult {
@@ -13,28 +24,94 @@ pub struct ProcessResult {
 }
 
 impl Resampler {
-    pub fn new() -> Self {
-        let rate = bark_protocol::SAMPLE_RATE.0 as f64;
-        let soxr = Soxr::variable_rate(rate, rate).unwrap();
-        Resampler { soxr }
+    /// Creates a resampler from `input_rate` to the sample rate of `output`,
+    /// for audio with the channel count of `output`
+    pub fn new(input_rate: SampleRate, output: AudioFormat) -> Self {
+        let input = input_rate.0 as f64;
+        let output_rate = output.sample_rate.0 as f64;
+
+        let pair_count = usize::from(output.channels).div_ceil(2);
+
+        let mut pairs = Vec::new();
+        for _ in 0..pair_count {
+            let soxr = Soxr::variable_rate(input, output_rate).unwrap();
+            let Ok(()) = pairs.push(soxr) else {
+                unreachable!("output channels exceeds MAX_CHANNELS");
+            };
+        }
+
+        Resampler {
+            channels: output.channels,
+            output_rate: output.sample_rate,
+            pairs,
+        }
     }
 
-    pub fn set_input_rate(&mut self, rate: u32) -> Result<(), soxr::Error> {
-        let input = rate as f64;
-        let output = bark_protocol::SAMPLE_RATE.0 as f64;
-        self.soxr.set_rates(input, output, 0)
+    /// Changes the input sample rate, which need not be a whole number of
+    /// hertz so that small adjustments can be made precisely
+    pub fn set_input_rate(&mut self, input: f64) -> Result<(), soxr::Error> {
+        let output = self.output_rate.0 as f64;
+
+        for soxr in &mut self.pairs {
+            soxr.set_rates(input, output, 0)?;
+        }
+
+        Ok(())
     }
 
-    pub fn process(&mut self, input: &[Frame], output: &mut [Frame])
+    /// Resamples up to one packet of interleaved audio from `input` into
+    /// `output`, both with the channel count this resampler was created with
+    pub fn process(&mut self, input: &[Sample], output: &mut [Sample])
         -> Result<ProcessResult, soxr::Error>
     {
-        let input = bytemuck::must_cast_slice(input);
-        let output = bytemuck::must_cast_slice_mut(output);
-        let result = self.soxr.process(input, output)?;
-
-        Ok(ProcessResult {
-            input_read: FrameCount(result.input_frames),
-            output_written: FrameCount(result.output_frames),
-        })
+        let channels = usize::from(self.channels);
+
+        let input_frames = FrameCount::of(input, self.channels).0;
+        assert!(input_frames <= FRAMES_PER_PACKET);
+
+        let output_frames = std::cmp::min(
+            FrameCount::of(output, self.channels).0,
+            MAX_OUTPUT_FRAMES_PER_PACKET);
+
+        let mut result = ProcessResult {
+            input_read: FrameCount(0),
+            output_written: FrameCount(0),
+        };
+
+        for (pair, soxr) in self.pairs.iter_mut().enumerate() {
+            let left = pair * 2;
+            let right = left + 1;
+
+            // deinterleave this pair of channels, padding with silence if
+            // we have an odd number of channels:
+            let mut pair_input = [StereoFrame::default(); FRAMES_PER_PACKET];
+            for (frame, out) in input.chunks_exact(channels).zip(&mut pair_input) {
+                *out = [frame[left], frame.get(right).copied().unwrap_or(0.0)];
+            }
+
+            let mut pair_output = [StereoFrame::default(); MAX_OUTPUT_FRAMES_PER_PACKET];
+
+            let processed = soxr.process(
+                bytemuck::must_cast_slice(&pair_input[0..input_frames]),
+                bytemuck::must_cast_slice_mut(&mut pair_output[0..output_frames]),
+            )?;
+
+            // and interleave the resampled pair back into the output:
+            let written = &pair_output[0..processed.output_frames];
+            for (frame, resampled) in output.chunks_exact_mut(channels).zip(written) {
+                frame[left] = resampled[0];
+
+                if let Some(sample) = frame.get_mut(right) {
+                    *sample = resampled[1];
+                }
+            }
+
+            result = ProcessResult {
+                input_read: FrameCount(processed.input_frames),
+                output_written: FrameCount(processed.output_frames),
+            };
+        }
+
+        Ok(result)
     }
 }
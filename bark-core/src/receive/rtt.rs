@@ -0,0 +1,120 @@
+use core::time::Duration;
+
+/// Number of recent round trips the minimum is taken over. Rejected round
+/// trips count too, so that if the path to the source gets slower for good,
+/// the minimum follows it once the faster round trips age out
+const WINDOW: usize = 32;
+
+/// Round trips are accepted within this much of the minimum, however small
+/// the minimum is. Scheduling jitter alone adds about this much
+const MIN_TOLERANCE: Duration = Duration::from_micros(500);
+
+/// Round trips are also accepted within the minimum divided by this, ie.
+/// within half the minimum, for paths slow enough that a fixed tolerance
+/// would reject nearly everything
+const TOLERANCE_DIVISOR: u32 = 2;
+
+/// Filters time exchanges by round trip time. A round trip well above the
+/// recent minimum was held up somewhere along the way, a Wi-Fi retry or a
+/// busy queue, and almost never evenly in both directions. The clock delta
+/// measured from it is off by up to half the hold up, so it is better left
+/// out.
+pub struct RttFilter {
+    samples: [Duration; WINDOW],
+    count: usize,
+    index: usize,
+}
+
+impl RttFilter {
+    pub fn new() -> Self {
+        RttFilter {
+            samples: [Duration::ZERO; WINDOW],
+            count: 0,
+            index: 0,
+        }
+    }
+
+    /// Adds a round trip time to the window, returning whether the time
+    /// exchange it came from should be used
+    pub fn observe(&mut self, rtt: Duration) -> bool {
+        self.samples[self.index] = rtt;
+
+        if self.count < WINDOW {
+            self.count += 1;
+        }
+
+        self.index += 1;
+        self.index %= WINDOW;
+
+        let min = self.min_rtt().unwrap_or(rtt);
+        let tolerance = (min / TOLERANCE_DIVISOR).max(MIN_TOLERANCE);
+
+        rtt <= min + tolerance
+    }
+
+    /// Shortest round trip in the window
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.samples[0..self.count].iter().copied().min()
+    }
+}
+
+impl Default for RttFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn accepts_within_tolerance_of_minimum() {
+        let mut filter = RttFilter::new();
+        assert!(filter.observe(millis(4)));
+
+        // tolerance is half the 4ms minimum
+        assert!(filter.observe(millis(6)));
+        assert!(!filter.observe(millis(6) + Duration::from_micros(1)));
+        assert_eq!(filter.min_rtt(), Some(millis(4)));
+    }
+
+    #[test]
+    fn tolerance_has_a_floor() {
+        let mut filter = RttFilter::new();
+        assert!(filter.observe(Duration::from_micros(200)));
+
+        // half the minimum would be 100us, the floor allows 500us
+        assert!(filter.observe(Duration::from_micros(700)));
+        assert!(!filter.observe(Duration::from_micros(701)));
+    }
+
+    #[test]
+    fn new_minimum_is_always_accepted() {
+        let mut filter = RttFilter::new();
+        assert!(filter.observe(millis(40)));
+        assert!(filter.observe(millis(2)));
+        assert!(!filter.observe(millis(40)));
+    }
+
+    #[test]
+    fn minimum_rises_once_fast_samples_leave_window() {
+        let mut filter = RttFilter::new();
+        assert!(filter.observe(millis(2)));
+
+        // the path gets slower for good. rejected at first, until the fast
+        // round trip ages out of the window
+        for _ in 0..WINDOW - 1 {
+            assert!(!filter.observe(millis(10)));
+        }
+
+        assert_eq!(filter.min_rtt(), Some(millis(2)));
+
+        assert!(filter.observe(millis(10)));
+        assert_eq!(filter.min_rtt(), Some(millis(10)));
+    }
+}
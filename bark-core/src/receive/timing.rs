@@ -1,10 +1,67 @@
 use core::time::Duration;
 
-use bark_protocol::time::{Timestamp, SampleDuration};
-use bark_protocol::SampleRate;
+use bark_protocol::time::{Timestamp, TimestampDelta};
+use bark_protocol::{SampleRate, SAMPLE_RATE};
 
+/// Time constant of the low pass filter applied to measured offsets, which
+/// smooths out network and scheduling jitter before it reaches the playback
+/// rate. Much shorter than the controller takes to respond, so that it
+/// doesn't destabilise it
+const OFFSET_FILTER: Duration = Duration::from_millis(200);
+
+/// Longest gap between timings that is integrated over. Gaps are normally
+/// a packet long, anything longer means we haven't been playing
+const MAX_STEP: Duration = Duration::from_millis(100);
+
+/// Tuning parameters for [`RateAdjust`]
+#[derive(Debug, Clone, Copy)]
+pub struct RateParams {
+    /// Proportional gain, the fractional rate adjustment made per second of
+    /// offset from the stream
+    pub kp: f64,
+    /// Integral gain, the fractional rate adjustment accumulated per second
+    /// of offset, per second it persists. This learns the steady skew
+    /// between our output clock and the stream's
+    pub ki: f64,
+    /// Offset beyond which the stream is reported as slewing
+    pub slew_start: Duration,
+    /// Offset within which the stream is reported as back in sync
+    pub slew_stop: Duration,
+    /// Largest fractional adjustment made to the playback rate
+    pub max_adjust: f64,
+    /// Offset beyond which playback jumps to the stream, rather than slewing
+    /// to it over what could be a long time
+    pub resync_threshold: Duration,
+}
+
+impl Default for RateParams {
+    fn default() -> Self {
+        RateParams {
+            kp: 2.0,
+            ki: 0.1,
+            slew_start: Duration::from_micros(2000),
+            slew_stop: Duration::from_micros(1000),
+            max_adjust: 0.01,
+            resync_threshold: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Adjusts the playback rate of the stream to keep it in sync, with a PI
+/// controller driven by the offset between when audio should play and
+/// when it actually will
 pub struct RateAdjust {
+    base_rate: SampleRate,
+    params: RateParams,
+    // filtered offset in seconds, None until the first timing
+    offset: Option<f64>,
+    // integral term, the fractional rate adjustment built up to cancel out
+    // steady skew between clocks
+    integral: f64,
+    last_real: Option<Timestamp>,
     slew: bool,
+    // offset in seconds to jump by, if we're too far off to slew
+    resync: Option<f64>,
 }
 
 #[derive(Copy, Clone)]
@@ -14,9 +71,15 @@ pub struct Timing {
 }
 
 impl RateAdjust {
-    pub fn new() -> Self {
+    pub fn new(base_rate: SampleRate, params: RateParams) -> Self {
         RateAdjust {
-            slew: false
+            base_rate,
+            params,
+            offset: None,
+            integral: 0.0,
+            last_real: None,
+            slew: false,
+            resync: None,
         }
     }
 
@@ -24,44 +87,200 @@ impl RateAdjust {
         self.slew
     }
 
-    pub fn sample_rate(&mut self, timing: Timing) -> SampleRate {
-        self.adjusted_rate(timing).unwrap_or(bark_protocol::SAMPLE_RATE)
+    /// Estimated skew between our output clock and the stream's, as the
+    /// fractional rate adjustment needed to cancel it out
+    pub fn skew(&self) -> f64 {
+        self.integral
     }
 
-    fn adjusted_rate(&mut self, timing: Timing) -> Option<SampleRate> {
-        // parameters, maybe these could be cli args?
-        let start_slew_threshold = Duration::from_micros(2000);
-        let stop_slew_threshold = Duration::from_micros(1000);
-        let slew_target_duration = Duration::from_millis(500);
+    /// Offset in seconds that playback should jump by, if the stream has
+    /// got too far off to slew to. Positive when we are behind the stream
+    pub fn take_resync(&mut self) -> Option<f64> {
+        self.resync.take()
+    }
+
+    /// Input sample rate to resample the stream at so that it converges on
+    /// `timing`
+    pub fn sample_rate(&mut self, timing: Timing) -> f64 {
+        f64::from(self.base_rate.0) * (1.0 + self.adjustment(timing))
+    }
+
+    fn adjustment(&mut self, timing: Timing) -> f64 {
+        let measured = seconds(timing.real.delta(timing.play));
+
+        let step = self.last_real
+            .map(|last| seconds(timing.real.delta(last)))
+            .unwrap_or(0.0)
+            .clamp(0.0, MAX_STEP.as_secs_f64());
+
+        self.last_real = Some(timing.real);
+
+        let alpha = (step / OFFSET_FILTER.as_secs_f64()).min(1.0);
 
-        // turn them into native units
-        let start_slew_threshold = SampleDuration::from_std_duration_lossy(start_slew_threshold);
-        let stop_slew_threshold = SampleDuration::from_std_duration_lossy(stop_slew_threshold);
+        let offset = match self.offset {
+            Some(offset) => offset + (measured - offset) * alpha,
+            None => measured,
+        };
 
-        let frame_offset = timing.real.delta(timing.play);
+        self.offset = Some(offset);
 
-        if frame_offset.abs() < stop_slew_threshold {
+        // hysteresis between thresholds stops the reported status flapping
+        // when the offset hovers around one of them
+        if offset.abs() >= self.params.slew_start.as_secs_f64() {
+            self.slew = true;
+        } else if offset.abs() < self.params.slew_stop.as_secs_f64() {
             self.slew = false;
-            return None;
         }
 
-        if frame_offset.abs() < start_slew_threshold && !self.slew {
-            return None;
+        if offset.abs() >= self.params.resync_threshold.as_secs_f64() {
+            // playback is about to jump, so the offset will change all at
+            // once. start filtering it afresh after. the integral is kept,
+            // clock skew hasn't changed
+            self.resync = Some(offset);
+            self.offset = None;
+            return self.integral;
         }
 
-        let slew_duration_duration = i64::try_from(slew_target_duration.as_micros()).unwrap();
-        let base_sample_rate = i64::from(bark_protocol::SAMPLE_RATE);
-        let rate_offset = frame_offset.as_frames() * 1_000_000 / slew_duration_duration;
-        let rate = base_sample_rate + rate_offset;
+        let max_adjust = self.params.max_adjust;
+        let proportional = self.params.kp * offset;
+        let integral = self.integral + self.params.ki * offset * step;
+
+        // only integrate while the output isn't saturated, or while doing so
+        // pulls it back from saturation. otherwise the integral winds up
+        // while we're far from the stream, and overshoots once we're close
+        let saturated = (proportional + integral).abs() > max_adjust;
+        let unwinding = integral.abs() < self.integral.abs();
+
+        if !saturated || unwinding {
+            self.integral = integral.clamp(-max_adjust, max_adjust);
+        }
+
+        (proportional + self.integral)
+            .clamp(-max_adjust, max_adjust)
+    }
+}
+
+fn seconds(delta: TimestampDelta) -> f64 {
+    delta.as_frames() as f64 / f64::from(SAMPLE_RATE.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use bark_protocol::types::TimestampMicros;
+
+    use crate::receive::testing;
+
+    use super::*;
+
+    // one packet of audio at 48khz
+    const STEP_USEC: u64 = 2500;
+
+    fn timestamp(usec: f64) -> Timestamp {
+        Timestamp::from_micros_lossy(TimestampMicros::new(usec as u64))
+    }
 
-        // clamp any potential slow down to 1%, we shouldn't ever get too far
-        // ahead of the stream
-        let rate = std::cmp::max(base_sample_rate * 99 / 100, rate);
+    /// Simulates playback for `seconds`, starting `offset_usec` behind the
+    /// stream with an output clock running `skew` fast, and measuring offset
+    /// with `noise_usec` of jitter. Returns the final true offset in
+    /// microseconds along with the number of times slew status changed
+    fn simulate(rate: &mut RateAdjust, seconds: u64, offset_usec: f64, skew: f64, noise_usec: f64) -> (f64, usize) {
+        let base_rate = 48000.0;
+
+        let mut real = 1_000_000_000.0;
+        let mut play = real - offset_usec;
+        let mut slew = rate.slew();
+        let mut changes = 0;
+
+        let mut noise = testing::noise(noise_usec);
+
+        for _ in 0..(seconds * 1_000_000 / STEP_USEC) {
+            let sample_rate = rate.sample_rate(Timing {
+                real: timestamp(real),
+                play: timestamp(play + noise()),
+            });
+
+            // we consume stream audio at the adjusted rate, through an
+            // output clock which runs at its own pace
+            let step = STEP_USEC as f64;
+            real += step;
+            play += step * (sample_rate / base_rate) * (1.0 + skew);
+
+            if rate.slew() != slew {
+                slew = rate.slew();
+                changes += 1;
+            }
+        }
+
+        (real - play, changes)
+    }
+
+    fn rate_adjust() -> RateAdjust {
+        RateAdjust::new(SampleRate(48000), RateParams::default())
+    }
+
+    #[test]
+    fn converges_from_offset() {
+        let mut rate = rate_adjust();
+
+        let (offset, _) = simulate(&mut rate, 20, 50_000.0, 0.0, 0.0);
+        assert!(offset.abs() < 100.0, "offset {offset}us after 20s");
+        assert!(!rate.slew());
+    }
+
+    #[test]
+    fn slews_while_converging() {
+        let mut rate = rate_adjust();
+
+        simulate(&mut rate, 1, 50_000.0, 0.0, 0.0);
+        assert!(rate.slew());
+    }
+
+    #[test]
+    fn tracks_clock_skew() {
+        let mut rate = rate_adjust();
+        let skew = 80e-6;
+
+        let (offset, _) = simulate(&mut rate, 120, 0.0, skew, 0.0);
+        // timestamps are only accurate to a frame, about 20us
+        assert!(offset.abs() < 50.0, "offset {offset}us with skew");
+
+        // integral term should have learned to cancel out the skew
+        let learned = rate.skew() + skew;
+        assert!(learned.abs() < 2e-6, "skew estimate off by {learned}");
+    }
+
+    #[test]
+    fn stays_in_sync_through_jitter() {
+        let mut rate = rate_adjust();
+
+        let (offset, changes) = simulate(&mut rate, 60, 0.0, 20e-6, 3000.0);
+        assert!(offset.abs() < 500.0, "offset {offset}us with jitter");
+        assert_eq!(changes, 0);
+    }
+
+    #[test]
+    fn resyncs_beyond_threshold() {
+        let mut rate = rate_adjust();
+
+        let real = timestamp(1_000_000_000.0);
+        let play = timestamp(1_000_000_000.0 - 300_000.0);
+
+        let sample_rate = rate.sample_rate(Timing { real, play });
+        assert_eq!(sample_rate, 48000.0);
+
+        let resync = rate.take_resync().expect("resync");
+        assert!((resync - 0.3).abs() < 1e-3, "resync by {resync}s");
+        assert!(rate.take_resync().is_none());
+    }
 
-        // let the speed up run much higher, but keep it reasonable still
-        let rate = std::cmp::min(base_sample_rate * 101 / 100, rate);
+    #[test]
+    fn clamps_rate_adjustment() {
+        let mut rate = rate_adjust();
+        // far enough off to saturate, but not to resync
+        let real = timestamp(1_000_000_000.0);
+        let play = timestamp(1_000_000_000.0 - 50_000.0);
 
-        self.slew = true;
-        Some(SampleRate(u32::try_from(rate).unwrap()))
+        let sample_rate = rate.sample_rate(Timing { real, play });
+        assert!((sample_rate - 48480.0).abs() < 1e-6, "rate {sample_rate}");
     }
 }
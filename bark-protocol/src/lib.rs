@@ -1,23 +1,47 @@
 #![no_std]
 
+use core::fmt::{self, Display};
+
+use bytemuck::{Pod, Zeroable};
 use derive_more::Into;
 
+use time::SampleDuration;
+use types::ProtocolVersion;
+
 pub mod buffer;
 pub mod packet;
 pub mod time;
 pub mod types;
 
+/// Protocol version spoken by this build. Bump this whenever the wire format
+/// changes incompatibly.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(4);
+
+/// Oldest protocol version this build can still understand. Version 4 added
+/// stream ids to audio packets, changing their layout.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(4);
+
+/// Sample rate of streams which don't specify one. This is also the implicit
+/// denominator of `Timestamp` and `SampleDuration`, regardless of the sample
+/// rate of the stream.
 pub const SAMPLE_RATE: SampleRate = SampleRate(48000);
+/// Channel count of streams which don't specify one
 pub const CHANNELS: ChannelCount = ChannelCount(2);
 pub const FRAMES_PER_PACKET: usize = 120; // 2.5ms at 48khz, compatible with opus
-pub const SAMPLES_PER_PACKET: usize = CHANNELS.0 as usize * FRAMES_PER_PACKET;
 
-#[derive(Copy, Clone, Debug, Into)]
+pub const MIN_SAMPLE_RATE: SampleRate = SampleRate(8000);
+pub const MAX_SAMPLE_RATE: SampleRate = SampleRate(192000);
+pub const MAX_CHANNELS: ChannelCount = ChannelCount(8);
+pub const MAX_SAMPLES_PER_PACKET: usize = MAX_CHANNELS.0 as usize * FRAMES_PER_PACKET;
+
+#[derive(Copy, Clone, Debug, Into, PartialEq, Eq, Zeroable, Pod)]
 #[into(types(u64, u128, i64, f64))]
+#[repr(transparent)]
 pub struct SampleRate(pub u32);
 
-#[derive(Copy, Clone, Debug, Into)]
+#[derive(Copy, Clone, Debug, Into, PartialEq, Eq, Zeroable, Pod)]
 #[into(types(usize, u32, u64))]
+#[repr(transparent)]
 pub struct ChannelCount(pub u16);
 
 impl From<SampleRate> for usize {
@@ -25,3 +49,43 @@ impl From<SampleRate> for usize {
         value.0.try_into().expect("SampleRate -> usize")
     }
 }
+
+/// Sample rate and channel layout of a stream of interleaved audio
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: SampleRate,
+    pub channels: ChannelCount,
+}
+
+impl AudioFormat {
+    pub const DEFAULT: AudioFormat = AudioFormat {
+        sample_rate: SAMPLE_RATE,
+        channels: CHANNELS,
+    };
+
+    /// Whether this format is within the limits supported by the protocol
+    pub fn is_supported(&self) -> bool {
+        let rate_ok = self.sample_rate.0 >= MIN_SAMPLE_RATE.0
+            && self.sample_rate.0 <= MAX_SAMPLE_RATE.0;
+
+        let channels_ok = self.channels.0 >= 1
+            && self.channels.0 <= MAX_CHANNELS.0;
+
+        rate_ok && channels_ok
+    }
+
+    pub fn samples_per_packet(&self) -> usize {
+        usize::from(self.channels) * FRAMES_PER_PACKET
+    }
+
+    /// Duration of audio contained in a single packet
+    pub fn packet_duration(&self) -> SampleDuration {
+        SampleDuration::from_frames_at(FRAMES_PER_PACKET as u64, self.sample_rate)
+    }
+}
+
+impl Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz, {} ch", self.sample_rate.0, self.channels.0)
+    }
+}
@@ -3,16 +3,22 @@ use core::ops::Range;
 
 use bytemuck::Zeroable;
 
-use crate::SAMPLES_PER_PACKET;
+use crate::MAX_SAMPLES_PER_PACKET;
 use crate::buffer::{AllocError, PacketBuffer};
+use crate::types::stats::clock::ClockStats;
 use crate::types::stats::node::NodeStats;
 use crate::types::stats::receiver::ReceiverStats;
-use crate::types::{self, Magic, SessionId, StatsReplyFlags, AudioPacketHeader};
+use crate::types::{self, ControlAction, Magic, SessionId, StatsReplyFlags, AudioPacketHeader, ProtocolVersion};
 
+// parity packets are the largest we send, carrying the equivalent of a full
+// audio packet behind their own header
 pub const MAX_PACKET_SIZE: usize =
     size_of::<types::PacketHeader>() +
+    size_of::<types::ParityPacketHeader>() +
     size_of::<types::AudioPacketHeader>() +
-    size_of::<types::AudioPacketBuffer>();
+    size_of::<types::AudioPacketBuffer>() +
+    size_of::<types::SealTrailer>() +
+    size_of::<types::AuthTrailer>();
 
 #[derive(Debug)]
 pub struct Packet(PacketBuffer);
@@ -24,6 +30,7 @@ impl Packet {
 
         let mut packet = Packet(PacketBuffer::allocate(packet_len)?);
         packet.header_mut().magic = magic;
+        packet.header_mut().version = crate::PROTOCOL_VERSION;
         Ok(packet)
     }
 
@@ -41,11 +48,22 @@ impl Packet {
     }
 
     pub fn parse(self) -> Option<PacketKind> {
+        // we have no idea how to interpret packets from incompatible
+        // protocol versions, refuse to parse them at all:
+        if !self.version().is_compatible() {
+            return None;
+        }
+
         match self.header().magic {
             Magic::AUDIO => Audio::parse(self).map(PacketKind::Audio),
             Magic::TIME => Time::parse(self).map(PacketKind::Time),
             Magic::STATS_REQ => StatsRequest::parse(self).map(PacketKind::StatsRequest),
             Magic::STATS_REPLY => StatsReply::parse(self).map(PacketKind::StatsReply),
+            Magic::METADATA => Metadata::parse(self).map(PacketKind::Metadata),
+            Magic::RETRANSMIT => Retransmit::parse(self).map(PacketKind::Retransmit),
+            Magic::PARITY => Parity::parse(self).map(PacketKind::Parity),
+            Magic::CONTROL => Control::parse(self).map(PacketKind::Control),
+            Magic::VOLUME => Volume::parse(self).map(PacketKind::Volume),
             _ => None,
         }
     }
@@ -56,6 +74,10 @@ impl Packet {
         bytemuck::from_bytes(header_bytes)
     }
 
+    pub fn version(&self) -> ProtocolVersion {
+        self.header().version
+    }
+
     pub fn header_mut(&mut self) -> &mut types::PacketHeader {
         let header_size = size_of::<types::PacketHeader>();
         let header_bytes = &mut self.0.as_bytes_mut()[0..header_size];
@@ -84,6 +106,11 @@ pub enum PacketKind {
     Time(Time),
     StatsRequest(StatsRequest),
     StatsReply(StatsReply),
+    Metadata(Metadata),
+    Retransmit(Retransmit),
+    Parity(Parity),
+    Control(Control),
+    Volume(Volume),
 }
 
 #[derive(Debug)]
@@ -94,7 +121,7 @@ impl Audio {
         size_of::<types::AudioPacketHeader>();
 
     pub const MAX_BUFFER_LENGTH: usize =
-        size_of::<[f32; SAMPLES_PER_PACKET]>();
+        size_of::<[f32; MAX_SAMPLES_PER_PACKET]>();
 
     pub fn new(header: &AudioPacketHeader, data: &[u8]) -> Result<Audio, AllocError> {
         let length = Self::HEADER_LENGTH + data.len();
@@ -109,11 +136,17 @@ impl Audio {
             return None;
         }
 
-        if packet.header().flags != 0 {
+        if packet.header().flags.get() != 0 {
             return None;
         }
 
-        Some(Audio(packet))
+        let audio = Audio(packet);
+
+        if !audio.header().audio_format().is_supported() {
+            return None;
+        }
+
+        Some(audio)
     }
 
     pub fn as_packet(&self) -> &Packet {
@@ -151,20 +184,25 @@ pub struct Time(Packet);
 impl Time {
     // packet delay has a linear relationship to packet size - it's important
     // that time packets experience as similar delay as possible to audio
-    // packets for most accurate synchronisation, so we pad this packet out
-    // to the same size as the audio packet
-
-    // TODO fix this
-    // const LENGTH: usize = Audio::LENGTH;
+    // packets for most accurate synchronisation, so sources can pad this
+    // packet out to the same size as their audio packets
     const LENGTH: usize = size_of::<types::TimePacket>();
 
-    // time packets are padded so that they are
-    // the same length as audio packets:
+    // time data is at the start of the packet, followed by any padding
     const DATA_RANGE: Range<usize> =
         0..size_of::<types::TimePacket>();
 
     pub fn allocate() -> Result<Self, AllocError> {
-        Ok(Time(Packet::allocate(Magic::TIME, Self::LENGTH)?))
+        Self::allocate_padded(Self::LENGTH)
+    }
+
+    /// Allocates a time packet padded out to a body of `length` bytes, or
+    /// unpadded if `length` is shorter than the time data itself. Replies
+    /// are made by modifying the received packet in place, so they keep the
+    /// same padding
+    pub fn allocate_padded(length: usize) -> Result<Self, AllocError> {
+        let length = core::cmp::max(length, Self::LENGTH);
+        Ok(Time(Packet::allocate(Magic::TIME, length)?))
     }
 
     pub fn parse(packet: Packet) -> Option<Self> {
@@ -174,7 +212,7 @@ impl Time {
             return None;
         }
 
-        if packet.header().flags != 0 {
+        if packet.header().flags.get() != 0 {
             return None;
         }
 
@@ -207,7 +245,7 @@ impl StatsRequest {
             return None;
         }
 
-        if packet.header().flags != 0 {
+        if packet.header().flags.get() != 0 {
             return None;
         }
 
@@ -225,9 +263,14 @@ pub struct StatsReply(Packet);
 impl StatsReply {
     const LENGTH: usize = size_of::<types::StatsReplyPacket>();
 
+    /// Length of a stats reply as first sent in protocol version 4. Fields
+    /// are only ever appended after this, so that builds speaking the same
+    /// version can read each other's stats
+    const MIN_LENGTH: usize = Self::LENGTH - size_of::<ClockStats>();
+
     fn new(flags: StatsReplyFlags, data: types::StatsReplyPacket) -> Result<Self, AllocError> {
         let mut packet = Packet::allocate(Magic::STATS_REPLY, Self::LENGTH)?;
-        packet.header_mut().flags = bytemuck::cast(flags);
+        packet.header_mut().flags.set(flags.bits());
 
         let mut reply = StatsReply(packet);
         *reply.data_mut() = data;
@@ -235,28 +278,40 @@ impl StatsReply {
         Ok(reply)
     }
 
-    pub fn source(sid: SessionId, node: NodeStats) -> Result<Self, AllocError> {
+    pub fn source(sid: SessionId, zone: &str, node: NodeStats) -> Result<Self, AllocError> {
         let receiver = ReceiverStats::zeroed();
+        let clock = ClockStats::zeroed();
+
+        let mut data = types::StatsReplyPacket { sid, receiver, node, zone: [0; 32], clock };
+        data.set_zone(zone);
 
-        Self::new(
-            StatsReplyFlags::IS_STREAM,
-            types::StatsReplyPacket { sid, receiver, node },
-        )
+        Self::new(StatsReplyFlags::IS_STREAM, data)
     }
 
-    pub fn receiver(sid: SessionId, receiver: ReceiverStats, node: NodeStats) -> Result<Self, AllocError> {
-        Self::new(
-            StatsReplyFlags::IS_RECEIVER,
-            types::StatsReplyPacket { sid, receiver, node },
-        )
+    pub fn receiver(sid: SessionId, zone: &str, receiver: ReceiverStats, clock: ClockStats, node: NodeStats) -> Result<Self, AllocError> {
+        let mut data = types::StatsReplyPacket { sid, receiver, node, zone: [0; 32], clock };
+        data.set_zone(zone);
+
+        Self::new(StatsReplyFlags::IS_RECEIVER, data)
     }
 
     pub fn parse(packet: Packet) -> Option<Self> {
-        if packet.len() != Self::LENGTH {
+        if packet.len() < Self::MIN_LENGTH {
             return None;
         }
 
-        Some(StatsReply(packet))
+        if packet.len() >= Self::LENGTH {
+            // anything past what we know of was appended by a newer build
+            return Some(StatsReply(packet));
+        }
+
+        // from an older build, without the fields appended since. zero
+        // filling them leaves their flags clear, so they read as unknown
+        let mut reply = Packet::allocate(Magic::STATS_REPLY, Self::LENGTH).ok()?;
+        *reply.header_mut() = *packet.header();
+        reply.as_bytes_mut()[..packet.len()].copy_from_slice(packet.as_bytes());
+
+        Some(StatsReply(reply))
     }
 
     pub fn as_packet(&self) -> &Packet {
@@ -264,14 +319,216 @@ impl StatsReply {
     }
 
     pub fn flags(&self) -> types::StatsReplyFlags {
-        bytemuck::cast(self.0.header().flags)
+        types::StatsReplyFlags::from_bits_truncate(self.0.header().flags.get())
     }
 
     pub fn data(&self) -> &types::StatsReplyPacket {
-        bytemuck::from_bytes(self.0.as_bytes())
+        bytemuck::from_bytes(&self.0.as_bytes()[..Self::LENGTH])
     }
 
     pub fn data_mut(&mut self) -> &mut types::StatsReplyPacket {
+        bytemuck::from_bytes_mut(&mut self.0.as_bytes_mut()[..Self::LENGTH])
+    }
+}
+
+#[derive(Debug)]
+pub struct Metadata(Packet);
+
+impl Metadata {
+    const LENGTH: usize = size_of::<types::MetadataPacket>();
+
+    pub fn allocate() -> Result<Self, AllocError> {
+        Ok(Metadata(Packet::allocate(Magic::METADATA, Self::LENGTH)?))
+    }
+
+    pub fn parse(packet: Packet) -> Option<Self> {
+        if packet.len() != Self::LENGTH {
+            return None;
+        }
+
+        if packet.header().flags.get() != 0 {
+            return None;
+        }
+
+        Some(Metadata(packet))
+    }
+
+    pub fn as_packet(&self) -> &Packet {
+        &self.0
+    }
+
+    pub fn data(&self) -> &types::MetadataPacket {
+        bytemuck::from_bytes(self.0.as_bytes())
+    }
+
+    pub fn data_mut(&mut self) -> &mut types::MetadataPacket {
+        bytemuck::from_bytes_mut(self.0.as_bytes_mut())
+    }
+}
+
+#[derive(Debug)]
+pub struct Retransmit(Packet);
+
+impl Retransmit {
+    const LENGTH: usize = size_of::<types::RetransmitPacket>();
+
+    pub fn new(sid: SessionId, base_seq: u64) -> Result<Self, AllocError> {
+        let mut packet = Retransmit(Packet::allocate(Magic::RETRANSMIT, Self::LENGTH)?);
+
+        let data = packet.data_mut();
+        data.sid = sid;
+        data.base_seq.set(base_seq);
+        data.missing.set(0);
+
+        Ok(packet)
+    }
+
+    pub fn parse(packet: Packet) -> Option<Self> {
+        if packet.len() != Self::LENGTH {
+            return None;
+        }
+
+        if packet.header().flags.get() != 0 {
+            return None;
+        }
+
+        Some(Retransmit(packet))
+    }
+
+    pub fn as_packet(&self) -> &Packet {
+        &self.0
+    }
+
+    pub fn data(&self) -> &types::RetransmitPacket {
+        bytemuck::from_bytes(self.0.as_bytes())
+    }
+
+    pub fn data_mut(&mut self) -> &mut types::RetransmitPacket {
+        bytemuck::from_bytes_mut(self.0.as_bytes_mut())
+    }
+}
+
+#[derive(Debug)]
+pub struct Parity(Packet);
+
+impl Parity {
+    pub const HEADER_LENGTH: usize =
+        size_of::<types::ParityPacketHeader>();
+
+    pub fn new(header: &types::ParityPacketHeader, data: &[u8]) -> Result<Self, AllocError> {
+        let length = Self::HEADER_LENGTH + data.len();
+        let mut packet = Parity(Packet::allocate(Magic::PARITY, length)?);
+        *packet.header_mut() = *header;
+        packet.parity_bytes_mut().copy_from_slice(data);
+        Ok(packet)
+    }
+
+    pub fn parse(packet: Packet) -> Option<Self> {
+        if packet.len() <= Self::HEADER_LENGTH {
+            return None;
+        }
+
+        if packet.header().flags.get() != 0 {
+            return None;
+        }
+
+        Some(Parity(packet))
+    }
+
+    pub fn as_packet(&self) -> &Packet {
+        &self.0
+    }
+
+    pub fn parity_bytes(&self) -> &[u8] {
+        &self.0.as_bytes()[Self::HEADER_LENGTH..]
+    }
+
+    pub fn parity_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.0.as_bytes_mut()[Self::HEADER_LENGTH..]
+    }
+
+    pub fn header(&self) -> &types::ParityPacketHeader {
+        bytemuck::from_bytes(&self.0.as_bytes()[0..Self::HEADER_LENGTH])
+    }
+
+    pub fn header_mut(&mut self) -> &mut types::ParityPacketHeader {
+        bytemuck::from_bytes_mut(&mut self.0.as_bytes_mut()[0..Self::HEADER_LENGTH])
+    }
+}
+
+#[derive(Debug)]
+pub struct Control(Packet);
+
+impl Control {
+    const LENGTH: usize = size_of::<types::ControlPacket>();
+
+    pub fn new(sid: SessionId, seq: u64, action: ControlAction) -> Result<Self, AllocError> {
+        let mut packet = Control(Packet::allocate(Magic::CONTROL, Self::LENGTH)?);
+
+        let data = packet.data_mut();
+        data.sid = sid;
+        data.seq.set(seq);
+        data.action = action;
+
+        Ok(packet)
+    }
+
+    pub fn parse(packet: Packet) -> Option<Self> {
+        if packet.len() != Self::LENGTH {
+            return None;
+        }
+
+        if packet.header().flags.get() != 0 {
+            return None;
+        }
+
+        Some(Control(packet))
+    }
+
+    pub fn as_packet(&self) -> &Packet {
+        &self.0
+    }
+
+    pub fn data(&self) -> &types::ControlPacket {
+        bytemuck::from_bytes(self.0.as_bytes())
+    }
+
+    pub fn data_mut(&mut self) -> &mut types::ControlPacket {
+        bytemuck::from_bytes_mut(self.0.as_bytes_mut())
+    }
+}
+
+#[derive(Debug)]
+pub struct Volume(Packet);
+
+impl Volume {
+    const LENGTH: usize = size_of::<types::VolumePacket>();
+
+    pub fn allocate() -> Result<Self, AllocError> {
+        Ok(Volume(Packet::allocate(Magic::VOLUME, Self::LENGTH)?))
+    }
+
+    pub fn parse(packet: Packet) -> Option<Self> {
+        if packet.len() != Self::LENGTH {
+            return None;
+        }
+
+        if packet.header().flags.get() != 0 {
+            return None;
+        }
+
+        Some(Volume(packet))
+    }
+
+    pub fn as_packet(&self) -> &Packet {
+        &self.0
+    }
+
+    pub fn data(&self) -> &types::VolumePacket {
+        bytemuck::from_bytes(self.0.as_bytes())
+    }
+
+    pub fn data_mut(&mut self) -> &mut types::VolumePacket {
         bytemuck::from_bytes_mut(self.0.as_bytes_mut())
     }
 }
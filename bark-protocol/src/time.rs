@@ -1,6 +1,6 @@
 use crate::packet;
 use crate::types::TimestampMicros;
-use crate::{SAMPLE_RATE, FRAMES_PER_PACKET};
+use crate::{SAMPLE_RATE, SampleRate};
 
 /// A timestamp with implicit denominator SAMPLE_RATE
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -12,11 +12,11 @@ impl Timestamp {
         let micros = (ts * 1_000_000) / u128::from(SAMPLE_RATE.0);
         let micros = u64::try_from(micros)
             .expect("can't narrow timestamp to u64");
-        TimestampMicros(micros)
+        TimestampMicros::new(micros)
     }
 
     pub fn from_micros_lossy(micros: TimestampMicros) -> Timestamp {
-        let micros = u128::from(micros.0);
+        let micros = u128::from(micros.get());
         let ts = (micros * u128::from(SAMPLE_RATE.0)) / 1_000_000;
         let ts = u64::try_from(ts)
             .expect("can't narrow timestamp to u64");
@@ -55,8 +55,6 @@ impl Timestamp {
 pub struct SampleDuration(u64);
 
 impl SampleDuration {
-    pub const ONE_PACKET: SampleDuration = SampleDuration::from_frame_count(FRAMES_PER_PACKET as u64);
-
     pub const fn zero() -> Self {
         SampleDuration(0)
     }
@@ -69,6 +67,19 @@ impl SampleDuration {
         self.0
     }
 
+    /// Duration of `frames` frames of audio at the given sample rate
+    pub fn from_frames_at(frames: u64, rate: SampleRate) -> Self {
+        let duration = (u128::from(frames) * u128::from(SAMPLE_RATE)) / u128::from(rate);
+        let duration = u64::try_from(duration).expect("can't narrow duration to u64");
+        SampleDuration(duration)
+    }
+
+    /// Number of frames of audio at the given sample rate this duration spans
+    pub fn to_frames_at(self, rate: SampleRate) -> u64 {
+        let frames = (u128::from(self.0) * u128::from(rate)) / u128::from(SAMPLE_RATE);
+        u64::try_from(frames).expect("can't narrow frame count to u64")
+    }
+
     pub fn from_std_duration_lossy(duration: core::time::Duration) -> SampleDuration {
         let duration = (duration.as_micros() * u128::from(SAMPLE_RATE)) / 1_000_000;
         let duration = u64::try_from(duration).expect("can't narrow duration to u64");
@@ -95,6 +106,10 @@ impl SampleDuration {
 pub struct ClockDelta(i64);
 
 impl ClockDelta {
+    pub fn from_micros(micros: i64) -> ClockDelta {
+        ClockDelta(micros)
+    }
+
     pub fn as_micros(&self) -> i64 {
         self.0
     }
@@ -105,13 +120,13 @@ impl ClockDelta {
 
         // all fields should be non-zero here, it's a programming error if
         // they're not.
-        assert!(time.stream_1.0 != 0);
-        assert!(time.receive_2.0 != 0);
-        assert!(time.stream_3.0 != 0);
+        assert!(time.stream_1.get() != 0);
+        assert!(time.receive_2.get() != 0);
+        assert!(time.stream_3.get() != 0);
 
-        let t1_usec = time.stream_1.0 as i64;
-        let t2_usec = time.receive_2.0 as i64;
-        let t3_usec = time.stream_3.0 as i64;
+        let t1_usec = time.stream_1.get() as i64;
+        let t2_usec = time.receive_2.get() as i64;
+        let t3_usec = time.stream_3.get() as i64;
 
         // algorithm from the Precision Time Protocol page on Wikipedia
         ClockDelta((t2_usec - t1_usec + t2_usec - t3_usec) / 2)
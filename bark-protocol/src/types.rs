@@ -1,18 +1,26 @@
 use bytemuck::{Pod, Zeroable};
 
+pub mod le;
 pub mod stats;
 
-use crate::SAMPLES_PER_PACKET;
+use crate::{AudioFormat, ChannelCount, SampleRate, MAX_SAMPLES_PER_PACKET};
+
+use self::le::{I64Le, U16Le, U32Le, U64Le};
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct Magic(u32);
+pub struct Magic(U32Le);
 
 impl Magic {
-    pub const AUDIO: Magic       = Magic(0x00a79ae2);
-    pub const TIME: Magic        = Magic(0x01a79ae2);
-    pub const STATS_REQ: Magic   = Magic(0x02a79ae2);
-    pub const STATS_REPLY: Magic = Magic(0x03a79ae2);
+    pub const AUDIO: Magic       = Magic(U32Le::new(0x00a79ae2));
+    pub const TIME: Magic        = Magic(U32Le::new(0x01a79ae2));
+    pub const STATS_REQ: Magic   = Magic(U32Le::new(0x02a79ae2));
+    pub const STATS_REPLY: Magic = Magic(U32Le::new(0x03a79ae2));
+    pub const METADATA: Magic    = Magic(U32Le::new(0x04a79ae2));
+    pub const RETRANSMIT: Magic  = Magic(U32Le::new(0x05a79ae2));
+    pub const PARITY: Magic      = Magic(U32Le::new(0x06a79ae2));
+    pub const CONTROL: Magic     = Magic(U32Le::new(0x07a79ae2));
+    pub const VOLUME: Magic      = Magic(U32Le::new(0x08a79ae2));
 }
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
@@ -21,25 +29,100 @@ pub struct PacketHeader {
     // magic and flags. there is a distinct magic value for each packet type,
     // and flags has a packet-dependent meaning.
     pub magic: Magic,
-    pub flags: u32,
+    pub flags: U16Le,
+
+    // protocol version of the sending node. this occupies the upper half of
+    // what used to be a u32 flags field, which was always zero for the
+    // packet types that existed at the time, so nodes from before protocol
+    // versioning was introduced appear as version 0.
+    //
+    // the layout of this header must never change, so that nodes can always
+    // tell which version a peer is speaking.
+    pub version: ProtocolVersion,
+}
+
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ProtocolVersion(U16Le);
+
+impl ProtocolVersion {
+    /// Version of nodes which predate protocol versioning
+    pub const UNVERSIONED: ProtocolVersion = ProtocolVersion::new(0);
+
+    pub const fn new(version: u16) -> Self {
+        ProtocolVersion(U16Le::new(version))
+    }
+
+    pub const fn get(&self) -> u16 {
+        self.0.get()
+    }
+
+    /// Whether packets of this version can be understood by this build
+    pub fn compatibility(&self) -> Compatibility {
+        if *self < crate::MIN_PROTOCOL_VERSION {
+            Compatibility::TooOld
+        } else if *self > crate::PROTOCOL_VERSION {
+            Compatibility::TooNew
+        } else {
+            Compatibility::Compatible
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.compatibility() == Compatibility::Compatible
+    }
+}
+
+impl core::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if *self == ProtocolVersion::UNVERSIONED {
+            write!(f, "unversioned")
+        } else {
+            write!(f, "v{}", self.get())
+        }
+    }
+}
+
+impl PartialOrd for ProtocolVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ProtocolVersion {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible,
+    /// Peer speaks an older protocol than we support
+    TooOld,
+    /// Peer speaks a newer protocol than we know about
+    TooNew,
 }
 
 /// our network Packet struct
-/// we don't need to worry about endianness, because according to the rust docs:
-///
-///     Floats and Ints have the same endianness on all supported platforms.
-///     IEEE 754 very precisely specifies the bit layout of floats.
-///
-///     - https://doc.rust-lang.org/std/primitive.f32.html
+/// all multi-byte fields in wire structs are little endian types from the
+/// `le` module, so that hosts of either endianness agree on the layout
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
 pub struct AudioPacketHeader {
-    // stream id - set to the start time of a stream, used by receivers to
-    // detect new stream starts, used by senders to detect stream takeovers
+    // session id - set to the start time of the source process, unique to
+    // each source. time, metadata, parity and retransmit packets refer to
+    // the session they belong to
     pub sid: SessionId,
 
+    // stream id - set to the session id of the source that started the
+    // stream, used by receivers to detect new stream starts and by senders
+    // to detect stream takeovers. a standby source adopts the stream id of
+    // the source it fails over from, so receivers carry on playing
+    pub stream: StreamId,
+
     // packet sequence number - monotonic + gapless, arbitrary start point
-    pub seq: u64,
+    pub seq: U64Le,
 
     // presentation timestamp - used by receivers to detect + correct clock
     // drift
@@ -49,21 +132,63 @@ pub struct AudioPacketHeader {
     pub dts: TimestampMicros,
 
     pub format: AudioPacketFormat,
+
+    // sample format of the stream. these fields were introduced in protocol
+    // v2, v1 nodes leave them zeroed and always stream 48khz stereo.
+    pub channels: U16Le,
+    pub sample_rate: U32Le,
+
+    // zone this stream is playing in, receivers only play streams in the
+    // zone they follow
+    pub zone: ZoneId,
+
+    // priority of the stream source, used to decide takeovers when
+    // receivers and sources run with the priority takeover policy. this
+    // occupies what was padding, so sources which don't set it send 0
+    pub priority: U16Le,
+    pub _pad: [u8; 2],
+}
+
+impl AudioPacketHeader {
+    pub fn audio_format(&self) -> AudioFormat {
+        if self.channels.get() == 0 && self.sample_rate.get() == 0 {
+            return AudioFormat::DEFAULT;
+        }
+
+        AudioFormat {
+            sample_rate: SampleRate(self.sample_rate.get()),
+            channels: ChannelCount(self.channels.get()),
+        }
+    }
+
+    pub fn set_audio_format(&mut self, format: AudioFormat) {
+        self.sample_rate.set(format.sample_rate.0);
+        self.channels.set(format.channels.0);
+    }
 }
 
-/// This, regrettably, has to be a u64 to fill out `AudioPacketHeader` with
-/// no hidden padding. TODO this whole protocol tier needs a big rethink
 #[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct AudioPacketFormat(u64);
+pub struct AudioPacketFormat(U16Le);
 
 impl AudioPacketFormat {
-    pub const F32LE: Self = Self(1);
-    pub const S16LE: Self = Self(2);
-    pub const OPUS: Self = Self(3);
+    pub const F32LE: Self = Self(U16Le::new(1));
+    pub const S16LE: Self = Self(U16Le::new(2));
+    pub const OPUS: Self = Self(U16Le::new(3));
+}
+
+impl core::fmt::Display for AudioPacketFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            AudioPacketFormat::F32LE => write!(f, "f32le"),
+            AudioPacketFormat::S16LE => write!(f, "s16le"),
+            AudioPacketFormat::OPUS => write!(f, "opus"),
+            AudioPacketFormat(other) => write!(f, "unknown ({})", other.get()),
+        }
+    }
 }
 
-pub type AudioPacketBuffer = [f32; SAMPLES_PER_PACKET];
+pub type AudioPacketBuffer = [f32; MAX_SAMPLES_PER_PACKET];
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
@@ -71,6 +196,11 @@ pub struct TimePacket {
     pub sid: SessionId,
     pub rid: ReceiverId,
 
+    // zone of the stream, so that receivers only respond to time packets
+    // from the stream they follow
+    pub zone: ZoneId,
+    pub _pad: [u8; 4],
+
     pub stream_1: TimestampMicros,
     pub receive_2: TimestampMicros,
     pub stream_3: TimestampMicros,
@@ -78,6 +208,11 @@ pub struct TimePacket {
 
 #[derive(Debug, PartialEq)]
 pub enum TimePhase {
+    /// A receiver asks the stream server to start an exchange right away,
+    /// with `rid` set and no timestamps. Receivers send these in a burst
+    /// when a stream begins, so their clock estimate converges quickly
+    Request,
+
     /// The initial phase, the stream server sends out a broadcast time packet
     /// withn only `stream_1` set
     Broadcast,
@@ -91,9 +226,13 @@ pub enum TimePhase {
 
 impl TimePacket {
     pub fn phase(&self) -> Option<TimePhase> {
-        let t1 = self.stream_1.0;
-        let t2 = self.receive_2.0;
-        let t3 = self.stream_3.0;
+        let t1 = self.stream_1.get();
+        let t2 = self.receive_2.get();
+        let t3 = self.stream_3.get();
+
+        if t1 == 0 && t2 == 0 && t3 == 0 {
+            return Some(TimePhase::Request);
+        }
 
         if t1 != 0 && t2 == 0 && t3 == 0 {
             return Some(TimePhase::Broadcast);
@@ -112,45 +251,752 @@ impl TimePacket {
     }
 }
 
+/// Periodically broadcast by stream sources to describe the stream
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct MetadataPacket {
+    pub sid: SessionId,
+
+    // stream delay, the difference between pts and dts
+    pub delay: TimestampMicros,
+
+    // codec parameters, as in AudioPacketHeader
+    pub format: AudioPacketFormat,
+    pub channels: U16Le,
+    pub sample_rate: U32Le,
+
+    // nul padded UTF-8 strings, both may be empty
+    pub name: [u8; 64],
+    pub now_playing: [u8; 128],
+}
+
+impl MetadataPacket {
+    pub fn audio_format(&self) -> AudioFormat {
+        AudioFormat {
+            sample_rate: SampleRate(self.sample_rate.get()),
+            channels: ChannelCount(self.channels.get()),
+        }
+    }
+
+    pub fn set_audio_format(&mut self, format: AudioFormat) {
+        self.sample_rate.set(format.sample_rate.0);
+        self.channels.set(format.channels.0);
+    }
+
+    pub fn name(&self) -> &str {
+        read_fixed_str(&self.name)
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        write_fixed_str(&mut self.name, name);
+    }
+
+    pub fn now_playing(&self) -> &str {
+        read_fixed_str(&self.now_playing)
+    }
+
+    pub fn set_now_playing(&mut self, now_playing: &str) {
+        write_fixed_str(&mut self.now_playing, now_playing);
+    }
+}
+
+fn read_fixed_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter()
+        .position(|b| *b == 0)
+        .unwrap_or(bytes.len());
+
+    core::str::from_utf8(&bytes[0..len]).unwrap_or_default()
+}
+
+/// Writes as much of `s` as will fit into `buff`, without splitting a
+/// character, and nul pads the rest
+fn write_fixed_str(buff: &mut [u8], s: &str) {
+    let mut len = core::cmp::min(s.len(), buff.len());
+
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    buff.fill(0);
+    buff[0..len].copy_from_slice(&s.as_bytes()[0..len]);
+}
+
+/// Header of a forward error correction packet. The rest of the packet is
+/// the XOR of the bodies (header and buffer) of a group of consecutive audio
+/// packets, zero padded to the length of the longest
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct ParityPacketHeader {
+    pub sid: SessionId,
+
+    // seq of the first audio packet in the group
+    pub base_seq: U64Le,
+
+    // number of consecutive audio packets in the group
+    pub count: U16Le,
+
+    // XOR of the body lengths of all audio packets in the group
+    pub length: U16Le,
+
+    pub _pad: [u8; 4],
+}
+
+/// Sent by a receiver directly to the stream source, requesting that audio
+/// packets which never arrived be sent again
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct RetransmitPacket {
+    pub sid: SessionId,
+
+    // seq of the first packet covered by `missing`
+    pub base_seq: U64Le,
+
+    // bitmap of requested packets, bit n requests seq base_seq + n
+    pub missing: U64Le,
+}
+
+impl RetransmitPacket {
+    pub const MAX_SPAN: u64 = u64::BITS as u64;
+
+    /// Adds `seq` to the request, returning false if it lies outside the
+    /// range this packet can represent
+    pub fn request(&mut self, seq: u64) -> bool {
+        match seq.checked_sub(self.base_seq.get()) {
+            Some(offset) if offset < Self::MAX_SPAN => {
+                self.missing.set(self.missing.get() | (1 << offset));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn seqs(&self) -> impl Iterator<Item = u64> + '_ {
+        let base_seq = self.base_seq.get();
+        let missing = self.missing.get();
+
+        (0..Self::MAX_SPAN)
+            .filter(move |offset| missing & (1 << offset) != 0)
+            .map(move |offset| base_seq + offset)
+    }
+}
+
+/// Broadcast by a stream source to tell receivers about a change in the
+/// state of its stream
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct ControlPacket {
+    pub sid: SessionId,
+
+    // seq of the next audio packet the source would have sent. audio up to
+    // but not including this seq should still be played out
+    pub seq: U64Le,
+
+    pub action: ControlAction,
+    pub _pad: [u8; 6],
+}
+
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ControlAction(U16Le);
+
+impl ControlAction {
+    /// Source has stopped sending audio, receivers should fade out
+    pub const PAUSE: Self = Self(U16Le::new(1));
+    /// Source is sending audio again, receivers should fade back in
+    pub const RESUME: Self = Self(U16Le::new(2));
+    /// Source has stopped for good, receivers should fade out and go idle
+    pub const END: Self = Self(U16Le::new(3));
+}
+
+/// Sets the volume and mute state of receivers. Addressed to one receiver
+/// by id, or to all receivers on a host by hostname. A broadcast receiver id
+/// and an empty hostname match every receiver
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct VolumePacket {
+    pub rid: ReceiverId,
+
+    // nul padded UTF-8
+    pub hostname: [u8; 32],
+
+    // volume in percent, only applied if flags has SET_VOLUME
+    pub volume: u8,
+    pub flags: VolumeFlags,
+    pub _pad: [u8; 6],
+}
+
+impl VolumePacket {
+    pub fn hostname(&self) -> &str {
+        read_fixed_str(&self.hostname)
+    }
+
+    pub fn set_hostname(&mut self, hostname: &str) {
+        write_fixed_str(&mut self.hostname, hostname);
+    }
+
+    pub fn volume(&self) -> Option<u8> {
+        self.flags.contains(VolumeFlags::SET_VOLUME)
+            .then_some(self.volume)
+    }
+
+    pub fn mute(&self) -> Option<bool> {
+        self.flags.contains(VolumeFlags::SET_MUTE)
+            .then_some(self.flags.contains(VolumeFlags::MUTED))
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, Zeroable, Pod)]
+    #[repr(transparent)]
+    pub struct VolumeFlags: u8 {
+        const SET_VOLUME = 0x01;
+        const SET_MUTE   = 0x02;
+        const MUTED      = 0x04;
+    }
+}
+
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
 pub struct StatsReplyPacket {
     pub sid: SessionId,
     pub receiver: stats::receiver::ReceiverStats,
     pub node: stats::node::NodeStats,
+    // name of the zone the node streams to or follows, nul padded UTF-8
+    pub zone: [u8; 32],
+    // appended after the version 4 layout, see StatsReply::parse
+    pub clock: stats::clock::ClockStats,
+}
+
+impl StatsReplyPacket {
+    pub fn zone(&self) -> &str {
+        read_fixed_str(&self.zone)
+    }
+
+    pub fn set_zone(&mut self, zone: &str) {
+        write_fixed_str(&mut self.zone, zone);
+    }
 }
 
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy, Zeroable, Pod)]
     #[repr(transparent)]
-    pub struct StatsReplyFlags: u32 {
+    pub struct StatsReplyFlags: u16 {
         const IS_RECEIVER = 0x01;
         const IS_STREAM   = 0x02;
     }
 }
 
+/// Appended to every packet when nodes are configured with a pre-shared key.
+/// The MAC covers the entire packet, plus the sender, counter, and time
+/// fields of this trailer.
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct AuthTrailer {
+    // random identifier chosen by each node on startup, used to key replay
+    // protection state on receivers
+    pub sender: U64Le,
+
+    // incremented for every packet sent by a node, receivers reject
+    // packets with counters they have already seen
+    pub counter: U64Le,
+
+    // wall clock time the packet was signed at, in microseconds since the
+    // unix epoch. receivers reject packets too far from their own wall
+    // clock, so that old traffic can't be replayed to a receiver which
+    // hasn't seen the sender yet
+    pub time: U64Le,
+
+    // HMAC-SHA256 truncated to 128 bits
+    pub mac: [u8; 16],
+}
+
+/// Appended to the body of encrypted packets, before the `AuthTrailer`.
+/// Encrypted packets are sealed with XChaCha20-Poly1305, using the packet
+/// header as associated data, and the sender and counter from the packet's
+/// `AuthTrailer` as nonce.
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct SealTrailer {
+    pub tag: [u8; 16],
+}
+
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(transparent)]
-pub struct TimestampMicros(pub u64);
+pub struct TimestampMicros(U64Le);
+
+impl TimestampMicros {
+    pub const fn new(micros: u64) -> Self {
+        TimestampMicros(U64Le::new(micros))
+    }
+
+    pub const fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(transparent)]
-pub struct ReceiverId(pub u64);
+pub struct ReceiverId(U64Le);
 
 impl ReceiverId {
+    pub const fn new(id: u64) -> Self {
+        ReceiverId(U64Le::new(id))
+    }
+
+    pub const fn get(&self) -> u64 {
+        self.0.get()
+    }
+
     pub fn broadcast() -> Self {
-        ReceiverId(0)
+        ReceiverId::new(0)
     }
 
     pub fn is_broadcast(&self) -> bool {
-        self.0 == 0
+        self.get() == 0
     }
 
     pub fn matches(&self, this: &ReceiverId) -> bool {
-        self.is_broadcast() || self.0 == this.0
+        self.is_broadcast() || self.get() == this.get()
+    }
+}
+
+/// Identifies a zone, a set of receivers all playing the same stream.
+/// Multiple zones can share one multicast group. Zones are named by users,
+/// and identified on the wire by a hash of their name
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ZoneId(U32Le);
+
+impl ZoneId {
+    /// The zone of nodes with no zone configured, named by the empty string
+    pub const DEFAULT: ZoneId = ZoneId(U32Le::new(0));
+
+    pub fn from_name(name: &str) -> Self {
+        if name.is_empty() {
+            return ZoneId::DEFAULT;
+        }
+
+        // 32 bit FNV-1a
+        let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        });
+
+        ZoneId(U32Le::new(hash))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct SessionId(I64Le);
+
+impl SessionId {
+    pub const fn new(id: i64) -> Self {
+        SessionId(I64Le::new(id))
+    }
+
+    pub const fn get(&self) -> i64 {
+        self.0.get()
+    }
+}
+
+impl PartialOrd for SessionId {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        self.get().partial_cmp(&other.get())
     }
 }
 
-#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash)]
 #[repr(transparent)]
-pub struct SessionId(pub i64);
+pub struct StreamId(I64Le);
+
+impl StreamId {
+    pub const fn new(id: i64) -> Self {
+        StreamId(I64Le::new(id))
+    }
+
+    pub const fn get(&self) -> i64 {
+        self.0.get()
+    }
+}
+
+impl From<SessionId> for StreamId {
+    fn from(sid: SessionId) -> Self {
+        StreamId::new(sid.get())
+    }
+}
+
+impl PartialOrd for StreamId {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        self.get().partial_cmp(&other.get())
+    }
+}
+
+// the layout of wire structs is the protocol, make sure it never changes
+// by accident. all of these must also be free of padding, which bytemuck's
+// Pod derive checks for us
+const _: () = {
+    use core::mem::{align_of, size_of};
+
+    assert!(size_of::<PacketHeader>() == 8);
+    assert!(size_of::<AudioPacketHeader>() == 56);
+    assert!(size_of::<TimePacket>() == 48);
+    assert!(size_of::<MetadataPacket>() == 216);
+    assert!(size_of::<ParityPacketHeader>() == 24);
+    assert!(size_of::<RetransmitPacket>() == 24);
+    assert!(size_of::<ControlPacket>() == 24);
+    assert!(size_of::<VolumePacket>() == 48);
+    assert!(size_of::<StatsReplyPacket>() == 176);
+    assert!(size_of::<AuthTrailer>() == 40);
+    assert!(size_of::<SealTrailer>() == 16);
+
+    // byte alignment means wire structs can be read straight out of packet
+    // buffers at any offset
+    assert!(align_of::<PacketHeader>() == 1);
+    assert!(align_of::<AudioPacketHeader>() == 1);
+    assert!(align_of::<TimePacket>() == 1);
+    assert!(align_of::<StatsReplyPacket>() == 1);
+};
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use bytemuck::Pod;
+
+    use super::*;
+    use super::stats::clock::ClockStats;
+    use super::stats::node::NodeStats;
+    use super::stats::receiver::{ReceiverStats, StreamStatus};
+
+    const SID: [u8; 8] = [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
+    const RID: [u8; 8] = [0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11];
+    const ZONE: [u8; 4] = [0x44, 0x43, 0x42, 0x41];
+
+    fn sid() -> SessionId {
+        SessionId::new(0x0102030405060708)
+    }
+
+    fn rid() -> ReceiverId {
+        ReceiverId::new(0x1112131415161718)
+    }
+
+    fn zone() -> ZoneId {
+        ZoneId(U32Le::new(0x41424344))
+    }
+
+    fn padded<const N: usize>(s: &[u8]) -> [u8; N] {
+        let mut buf = [0; N];
+        buf[..s.len()].copy_from_slice(s);
+        buf
+    }
+
+    /// Asserts that `value` is laid out on the wire as `expected`, returning
+    /// it decoded back from those bytes
+    fn assert_layout<'a, T: Pod>(value: &T, expected: &'a [u8]) -> &'a T {
+        assert_eq!(bytemuck::bytes_of(value), expected);
+        bytemuck::from_bytes(expected)
+    }
+
+    #[test]
+    fn packet_header_layout() {
+        let header = PacketHeader {
+            magic: Magic::STATS_REPLY,
+            flags: U16Le::new(0x0102),
+            version: ProtocolVersion::new(4),
+        };
+
+        let expected = [0xe2, 0x9a, 0xa7, 0x03, 0x02, 0x01, 0x04, 0x00];
+
+        let decoded = assert_layout(&header, &expected);
+        assert_eq!(decoded.magic, Magic::STATS_REPLY);
+        assert_eq!(decoded.flags.get(), 0x0102);
+        assert_eq!(decoded.version.get(), 4);
+    }
+
+    #[test]
+    fn audio_packet_header_layout() {
+        let header = AudioPacketHeader {
+            sid: sid(),
+            stream: StreamId::new(-2),
+            seq: U64Le::new(0x2122232425262728),
+            pts: TimestampMicros::new(0x3132333435363738),
+            dts: TimestampMicros::new(0x4142434445464748),
+            format: AudioPacketFormat::OPUS,
+            channels: U16Le::new(2),
+            sample_rate: U32Le::new(48000),
+            zone: zone(),
+            priority: U16Le::new(0x0506),
+            _pad: [0; 2],
+        };
+
+        let expected = [
+            &SID[..],
+            &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            &[0x28, 0x27, 0x26, 0x25, 0x24, 0x23, 0x22, 0x21],
+            &[0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31],
+            &[0x48, 0x47, 0x46, 0x45, 0x44, 0x43, 0x42, 0x41],
+            &[0x03, 0x00],
+            &[0x02, 0x00],
+            &[0x80, 0xbb, 0x00, 0x00],
+            &ZONE,
+            &[0x06, 0x05],
+            &[0x00, 0x00],
+        ].concat();
+
+        let decoded = assert_layout(&header, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.stream, StreamId::new(-2));
+        assert_eq!(decoded.seq.get(), 0x2122232425262728);
+        assert_eq!(decoded.pts.get(), 0x3132333435363738);
+        assert_eq!(decoded.dts.get(), 0x4142434445464748);
+        assert_eq!(decoded.format, AudioPacketFormat::OPUS);
+        assert_eq!(decoded.channels.get(), 2);
+        assert_eq!(decoded.sample_rate.get(), 48000);
+        assert_eq!(decoded.zone, zone());
+        assert_eq!(decoded.priority.get(), 0x0506);
+    }
+
+    #[test]
+    fn time_packet_layout() {
+        let time = TimePacket {
+            sid: sid(),
+            rid: rid(),
+            zone: zone(),
+            _pad: [0; 4],
+            stream_1: TimestampMicros::new(0x2122232425262728),
+            receive_2: TimestampMicros::new(0x3132333435363738),
+            stream_3: TimestampMicros::new(0x4142434445464748),
+        };
+
+        let expected = [
+            &SID[..],
+            &RID,
+            &ZONE,
+            &[0x00; 4],
+            &[0x28, 0x27, 0x26, 0x25, 0x24, 0x23, 0x22, 0x21],
+            &[0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31],
+            &[0x48, 0x47, 0x46, 0x45, 0x44, 0x43, 0x42, 0x41],
+        ].concat();
+
+        let decoded = assert_layout(&time, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.rid.get(), rid().get());
+        assert_eq!(decoded.zone, zone());
+        assert_eq!(decoded.stream_1.get(), 0x2122232425262728);
+        assert_eq!(decoded.receive_2.get(), 0x3132333435363738);
+        assert_eq!(decoded.stream_3.get(), 0x4142434445464748);
+        assert_eq!(decoded.phase(), Some(TimePhase::StreamReply));
+    }
+
+    #[test]
+    fn metadata_packet_layout() {
+        let mut metadata = MetadataPacket {
+            sid: sid(),
+            delay: TimestampMicros::new(20_000),
+            format: AudioPacketFormat::S16LE,
+            channels: U16Le::new(1),
+            sample_rate: U32Le::new(44100),
+            name: [0; 64],
+            now_playing: [0; 128],
+        };
+
+        metadata.set_name("kitchen radio");
+        metadata.set_now_playing("song");
+
+        let expected = [
+            &SID[..],
+            &[0x20, 0x4e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0x02, 0x00],
+            &[0x01, 0x00],
+            &[0x44, 0xac, 0x00, 0x00],
+            &padded::<64>(b"kitchen radio"),
+            &padded::<128>(b"song"),
+        ].concat();
+
+        let decoded = assert_layout(&metadata, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.delay.get(), 20_000);
+        assert_eq!(decoded.format, AudioPacketFormat::S16LE);
+        assert_eq!(decoded.audio_format().channels.0, 1);
+        assert_eq!(decoded.audio_format().sample_rate.0, 44100);
+        assert_eq!(decoded.name(), "kitchen radio");
+        assert_eq!(decoded.now_playing(), "song");
+    }
+
+    #[test]
+    fn parity_packet_header_layout() {
+        let header = ParityPacketHeader {
+            sid: sid(),
+            base_seq: U64Le::new(0x2122232425262728),
+            count: U16Le::new(4),
+            length: U16Le::new(0x0a0b),
+            _pad: [0; 4],
+        };
+
+        let expected = [
+            &SID[..],
+            &[0x28, 0x27, 0x26, 0x25, 0x24, 0x23, 0x22, 0x21],
+            &[0x04, 0x00],
+            &[0x0b, 0x0a],
+            &[0x00; 4],
+        ].concat();
+
+        let decoded = assert_layout(&header, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.base_seq.get(), 0x2122232425262728);
+        assert_eq!(decoded.count.get(), 4);
+        assert_eq!(decoded.length.get(), 0x0a0b);
+    }
+
+    #[test]
+    fn retransmit_packet_layout() {
+        let retransmit = RetransmitPacket {
+            sid: sid(),
+            base_seq: U64Le::new(100),
+            missing: U64Le::new(0x8000000000000005),
+        };
+
+        let expected = [
+            &SID[..],
+            &[0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80],
+        ].concat();
+
+        let decoded = assert_layout(&retransmit, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.seqs().collect::<Vec<_>>(), [100, 102, 163]);
+    }
+
+    #[test]
+    fn control_packet_layout() {
+        let control = ControlPacket {
+            sid: sid(),
+            seq: U64Le::new(0x2122232425262728),
+            action: ControlAction::END,
+            _pad: [0; 6],
+        };
+
+        let expected = [
+            &SID[..],
+            &[0x28, 0x27, 0x26, 0x25, 0x24, 0x23, 0x22, 0x21],
+            &[0x03, 0x00],
+            &[0x00; 6],
+        ].concat();
+
+        let decoded = assert_layout(&control, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.seq.get(), 0x2122232425262728);
+        assert_eq!(decoded.action, ControlAction::END);
+    }
+
+    #[test]
+    fn volume_packet_layout() {
+        let mut volume = VolumePacket {
+            rid: rid(),
+            hostname: [0; 32],
+            volume: 75,
+            flags: VolumeFlags::SET_VOLUME | VolumeFlags::SET_MUTE,
+            _pad: [0; 6],
+        };
+
+        volume.set_hostname("lounge");
+
+        let expected = [
+            &RID[..],
+            &padded::<32>(b"lounge"),
+            &[75, 0x03],
+            &[0x00; 6],
+        ].concat();
+
+        let decoded = assert_layout(&volume, &expected);
+        assert_eq!(decoded.rid.get(), rid().get());
+        assert_eq!(decoded.hostname(), "lounge");
+        assert_eq!(decoded.volume(), Some(75));
+        assert_eq!(decoded.mute(), Some(false));
+    }
+
+    #[test]
+    fn stats_reply_packet_layout() {
+        let mut receiver = ReceiverStats::new();
+        receiver.set_stream(StreamStatus::Sync);
+        receiver.set_volume(50, true);
+        receiver.add_resync();
+        receiver.set_predict_offset(-250_000);
+
+        let mut clock = ClockStats::new();
+        clock.set_drift(1.5);
+        clock.add_rejected();
+
+        let node = NodeStats {
+            username: padded(b"bark"),
+            hostname: padded(b"lounge"),
+        };
+
+        let mut reply = StatsReplyPacket {
+            sid: sid(),
+            receiver,
+            node,
+            zone: [0; 32],
+            clock,
+        };
+
+        reply.set_zone("kitchen");
+
+        let expected = [
+            &SID[..],
+            // receiver: flags, status, volume, resyncs, pad
+            &[0xa1, 0x02, 50, 0x01, 0x00, 0x00, 0x00, 0x00],
+            // audio latency, buffer length, output latency, network latency
+            &[0x00; 32],
+            // predict offset, -0.25
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xd0, 0xbf],
+            &padded::<32>(b"bark"),
+            &padded::<32>(b"lounge"),
+            &padded::<32>(b"kitchen"),
+            // clock: flags, pad, rejected
+            &[0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+            // drift, 1.5
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f],
+            // convergence time, unset
+            &[0x00; 8],
+        ].concat();
+
+        let decoded = assert_layout(&reply, &expected);
+        assert_eq!(decoded.sid, sid());
+        assert_eq!(decoded.zone(), "kitchen");
+        assert!(matches!(decoded.receiver.stream(), Some(StreamStatus::Sync)));
+        assert_eq!(decoded.receiver.volume(), Some(50));
+        assert!(decoded.receiver.muted());
+        assert_eq!(decoded.receiver.resyncs(), 1);
+        assert_eq!(decoded.receiver.predict_offset(), Some(-0.25));
+        assert_eq!(decoded.receiver.audio_latency(), None);
+        assert_eq!(decoded.clock.drift(), Some(1.5));
+        assert_eq!(decoded.clock.rejected(), 1);
+        assert_eq!(decoded.clock.converge(), None);
+    }
+
+    #[test]
+    fn auth_trailer_layout() {
+        let trailer = AuthTrailer {
+            sender: U64Le::new(0x0102030405060708),
+            counter: U64Le::new(0x1112131415161718),
+            time: U64Le::new(0x2122232425262728),
+            mac: [0xaa; 16],
+        };
+
+        let expected = [
+            &SID[..],
+            &RID,
+            &[0x28, 0x27, 0x26, 0x25, 0x24, 0x23, 0x22, 0x21],
+            &[0xaa; 16],
+        ].concat();
+
+        let decoded = assert_layout(&trailer, &expected);
+        assert_eq!(decoded.sender.get(), 0x0102030405060708);
+        assert_eq!(decoded.counter.get(), 0x1112131415161718);
+        assert_eq!(decoded.time.get(), 0x2122232425262728);
+        assert_eq!(decoded.mac, [0xaa; 16]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Little endian integer and float types for use in wire structs.
+//!
+//! Every multi-byte field sent over the network is stored as one of these,
+//! so that packets can be cast directly to and from bytes with bytemuck on
+//! hosts of either endianness. They are byte arrays underneath, so they
+//! also have an alignment of 1 and can be read out of unaligned buffers.
+
+use core::fmt::{self, Debug};
+
+use bytemuck::{Pod, Zeroable};
+
+macro_rules! le_int {
+    ($name:ident, $native:ty) => {
+        #[derive(Clone, Copy, Zeroable, Pod, PartialEq, Eq, Hash, Default)]
+        #[repr(transparent)]
+        pub struct $name([u8; core::mem::size_of::<$native>()]);
+
+        impl $name {
+            pub const fn new(value: $native) -> Self {
+                $name(value.to_le_bytes())
+            }
+
+            pub const fn get(self) -> $native {
+                <$native>::from_le_bytes(self.0)
+            }
+
+            pub fn set(&mut self, value: $native) {
+                *self = Self::new(value);
+            }
+        }
+
+        impl From<$native> for $name {
+            fn from(value: $native) -> Self {
+                Self::new(value)
+            }
+        }
+
+        impl From<$name> for $native {
+            fn from(value: $name) -> Self {
+                value.get()
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&self.get(), f)
+            }
+        }
+    };
+}
+
+le_int!(U16Le, u16);
+le_int!(U32Le, u32);
+le_int!(U64Le, u64);
+le_int!(I64Le, i64);
+
+#[derive(Clone, Copy, Zeroable, Pod, PartialEq, Default)]
+#[repr(transparent)]
+pub struct F64Le([u8; 8]);
+
+impl F64Le {
+    pub fn new(value: f64) -> Self {
+        F64Le(value.to_le_bytes())
+    }
+
+    pub fn get(self) -> f64 {
+        f64::from_le_bytes(self.0)
+    }
+
+    pub fn set(&mut self, value: f64) {
+        *self = Self::new(value);
+    }
+}
+
+impl From<f64> for F64Le {
+    fn from(value: f64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<F64Le> for f64 {
+    fn from(value: F64Le) -> Self {
+        value.get()
+    }
+}
+
+impl Debug for F64Le {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.get(), f)
+    }
+}
+
+// the byte layout of these types is the wire format, check it at compile time
+const _: () = {
+    assert!(matches!(U16Le::new(0x0102).0, [0x02, 0x01]));
+    assert!(matches!(U32Le::new(0x01020304).0, [0x04, 0x03, 0x02, 0x01]));
+    assert!(matches!(U64Le::new(0x0102030405060708).0,
+        [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]));
+    assert!(matches!(I64Le::new(-2).0,
+        [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]));
+
+    assert!(U16Le::new(0x0102).get() == 0x0102);
+    assert!(U32Le::new(0x01020304).get() == 0x01020304);
+    assert!(U64Le::new(0x0102030405060708).get() == 0x0102030405060708);
+    assert!(I64Le::new(-2).get() == -2);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u16_layout() {
+        let value = U16Le::new(0x0102);
+        assert_eq!(bytemuck::bytes_of(&value), &[0x02, 0x01]);
+        assert_eq!(bytemuck::from_bytes::<U16Le>(&[0x02, 0x01]).get(), 0x0102);
+    }
+
+    #[test]
+    fn u32_layout() {
+        let value = U32Le::new(0x01020304);
+        assert_eq!(bytemuck::bytes_of(&value), &[0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(bytemuck::from_bytes::<U32Le>(&[0x04, 0x03, 0x02, 0x01]).get(), 0x01020304);
+    }
+
+    #[test]
+    fn u64_layout() {
+        let bytes = [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
+        let value = U64Le::new(0x0102030405060708);
+        assert_eq!(bytemuck::bytes_of(&value), &bytes);
+        assert_eq!(bytemuck::from_bytes::<U64Le>(&bytes).get(), 0x0102030405060708);
+    }
+
+    #[test]
+    fn i64_layout() {
+        let bytes = [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let value = I64Le::new(-2);
+        assert_eq!(bytemuck::bytes_of(&value), &bytes);
+        assert_eq!(bytemuck::from_bytes::<I64Le>(&bytes).get(), -2);
+    }
+
+    #[test]
+    fn f64_layout() {
+        // 1.5 is 0x3ff8000000000000
+        let bytes = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f];
+        let value = F64Le::new(1.5);
+        assert_eq!(bytemuck::bytes_of(&value), &bytes);
+        assert_eq!(bytemuck::from_bytes::<F64Le>(&bytes).get(), 1.5);
+    }
+
+    #[test]
+    fn set_replaces_value() {
+        let mut value = U32Le::new(1);
+        value.set(0xdeadbeef);
+        assert_eq!(bytemuck::bytes_of(&value), &[0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(u32::from(value), 0xdeadbeef);
+    }
+}
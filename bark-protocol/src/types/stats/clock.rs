@@ -0,0 +1,78 @@
+use bitflags::bitflags;
+use bytemuck::{Zeroable, Pod};
+
+use crate::types::le::{F64Le, U32Le};
+
+/// Stats on a receiver's clock sync with the stream. These come last in a
+/// stats reply, after everything in the original version 4 layout, so that
+/// builds which don't know about them can still read the rest
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct ClockStats {
+    flags: ClockStatsFlags,
+    _pad: [u8; 3],
+    rejected: U32Le,
+
+    drift: F64Le,
+    converge: F64Le,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Zeroable, Pod)]
+    #[repr(transparent)]
+    pub struct ClockStatsFlags: u8 {
+        const HAS_DRIFT    = 0x01;
+        const HAS_CONVERGE = 0x02;
+    }
+}
+
+impl ClockStats {
+    pub fn new() -> Self {
+        ClockStats::zeroed()
+    }
+
+    pub fn clear(&mut self) {
+        // the rejected count covers every stream, so survives a change
+        self.flags = ClockStatsFlags::empty();
+    }
+
+    /// Drift of the receiver's clock relative to the stream's in parts per
+    /// million, positive when the receiver's clock runs fast
+    pub fn drift(&self) -> Option<f64> {
+        self.flags.contains(ClockStatsFlags::HAS_DRIFT)
+            .then(|| self.drift.get())
+    }
+
+    /// Time in seconds from the stream beginning until the receiver's clock
+    /// estimate converged
+    pub fn converge(&self) -> Option<f64> {
+        self.flags.contains(ClockStatsFlags::HAS_CONVERGE)
+            .then(|| self.converge.get())
+    }
+
+    /// Number of time exchanges the receiver has left out of clock sync
+    /// for taking too long a round trip, since it started
+    pub fn rejected(&self) -> u32 {
+        self.rejected.get()
+    }
+
+    pub fn add_rejected(&mut self) {
+        self.rejected.set(self.rejected.get().wrapping_add(1));
+    }
+
+    pub fn set_drift(&mut self, ppm: f64) {
+        self.drift.set(ppm);
+        self.flags.insert(ClockStatsFlags::HAS_DRIFT);
+    }
+
+    pub fn set_converge(&mut self, elapsed: core::time::Duration) {
+        self.converge.set(elapsed.as_secs_f64());
+        self.flags.insert(ClockStatsFlags::HAS_CONVERGE);
+    }
+}
+
+impl Default for ClockStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,19 +2,22 @@ use bitflags::bitflags;
 use bytemuck::{Zeroable, Pod};
 
 use crate::time::{SampleDuration, Timestamp};
+use crate::types::le::{F64Le, U32Le};
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
 pub struct ReceiverStats {
     flags: ReceiverStatsFlags,
     stream_status: u8,
-    _pad: [u8; 6],
-
-    audio_latency: f64,
-    buffer_length: f64,
-    output_latency: f64,
-    network_latency: f64,
-    predict_offset: f64,
+    volume: u8,
+    resyncs: U32Le,
+    _pad: [u8; 1],
+
+    audio_latency: F64Le,
+    buffer_length: F64Le,
+    output_latency: F64Le,
+    network_latency: F64Le,
+    predict_offset: F64Le,
 }
 
 pub enum StreamStatus {
@@ -22,6 +25,8 @@ pub enum StreamStatus {
     Sync,
     Slew,
     Miss,
+    Paused,
+    Idle,
 }
 
 impl StreamStatus {
@@ -31,6 +36,8 @@ impl StreamStatus {
             StreamStatus::Sync => 2,
             StreamStatus::Slew => 3,
             StreamStatus::Miss => 4,
+            StreamStatus::Paused => 5,
+            StreamStatus::Idle => 6,
         }
     }
 
@@ -40,6 +47,8 @@ impl StreamStatus {
             2 => Some(StreamStatus::Sync),
             3 => Some(StreamStatus::Slew),
             4 => Some(StreamStatus::Miss),
+            5 => Some(StreamStatus::Paused),
+            6 => Some(StreamStatus::Idle),
             _ => None,
         }
     }
@@ -49,11 +58,13 @@ bitflags! {
     #[derive(Debug, Clone, Copy, Zeroable, Pod)]
     #[repr(transparent)]
     pub struct ReceiverStatsFlags: u8 {
+        const MUTED               = 0x01;
         const HAS_AUDIO_LATENCY   = 0x04;
         const HAS_BUFFER_LENGTH   = 0x08;
         const HAS_NETWORK_LATENCY = 0x10;
         const HAS_PREDICT_OFFSET  = 0x20;
         const HAS_OUTPUT_LATENCY  = 0x40;
+        const HAS_VOLUME          = 0x80;
     }
 }
 
@@ -71,13 +82,15 @@ impl ReceiverStats {
     }
 
     pub fn clear(&mut self) {
+        // volume belongs to the receiver rather than the stream, so it
+        // survives a change of stream
         self.set_stream(StreamStatus::Seek);
-        self.flags = ReceiverStatsFlags::empty();
+        self.flags &= ReceiverStatsFlags::HAS_VOLUME | ReceiverStatsFlags::MUTED;
     }
 
-    fn field(&self, flag: ReceiverStatsFlags, value: f64) -> Option<f64> {
+    fn field(&self, flag: ReceiverStatsFlags, value: F64Le) -> Option<f64> {
         if self.flags.contains(flag) {
-            Some(value)
+            Some(value.get())
         } else {
             None
         }
@@ -108,31 +121,57 @@ impl ReceiverStats {
         self.field(ReceiverStatsFlags::HAS_PREDICT_OFFSET, self.predict_offset)
     }
 
+    /// Output volume in percent
+    pub fn volume(&self) -> Option<u8> {
+        self.flags.contains(ReceiverStatsFlags::HAS_VOLUME)
+            .then_some(self.volume)
+    }
+
+    /// Number of times the receiver has jumped to resync with the stream,
+    /// since it started
+    pub fn resyncs(&self) -> u32 {
+        self.resyncs.get()
+    }
+
+    pub fn add_resync(&mut self) {
+        self.resyncs.set(self.resyncs.get().wrapping_add(1));
+    }
+
+    pub fn muted(&self) -> bool {
+        self.flags.contains(ReceiverStatsFlags::MUTED)
+    }
+
+    pub fn set_volume(&mut self, volume: u8, muted: bool) {
+        self.volume = volume;
+        self.flags.insert(ReceiverStatsFlags::HAS_VOLUME);
+        self.flags.set(ReceiverStatsFlags::MUTED, muted);
+    }
+
     pub fn set_audio_latency(&mut self, request_pts: Timestamp, packet_pts: Timestamp) {
-        let request_micros = request_pts.to_micros_lossy().0 as f64;
-        let packet_micros = packet_pts.to_micros_lossy().0 as f64;
+        let request_micros = request_pts.to_micros_lossy().get() as f64;
+        let packet_micros = packet_pts.to_micros_lossy().get() as f64;
 
-        self.audio_latency = (request_micros - packet_micros) / 1_000_000.0;
+        self.audio_latency.set((request_micros - packet_micros) / 1_000_000.0);
         self.flags.insert(ReceiverStatsFlags::HAS_AUDIO_LATENCY);
     }
 
     pub fn set_buffer_length(&mut self, length: SampleDuration) {
-        self.buffer_length = length.to_std_duration_lossy().as_micros() as f64 / 1_000_000.0;
+        self.buffer_length.set(length.to_std_duration_lossy().as_micros() as f64 / 1_000_000.0);
         self.flags.insert(ReceiverStatsFlags::HAS_BUFFER_LENGTH);
     }
 
     pub fn set_output_latency(&mut self, latency: SampleDuration) {
-        self.output_latency = latency.to_std_duration_lossy().as_micros() as f64 / 1_000_000.0;
+        self.output_latency.set(latency.to_std_duration_lossy().as_micros() as f64 / 1_000_000.0);
         self.flags.insert(ReceiverStatsFlags::HAS_OUTPUT_LATENCY);
     }
 
     pub fn set_network_latency(&mut self, latency: core::time::Duration) {
-        self.network_latency = latency.as_micros() as f64 / 1_000_000.0;
+        self.network_latency.set(latency.as_micros() as f64 / 1_000_000.0);
         self.flags.insert(ReceiverStatsFlags::HAS_NETWORK_LATENCY);
     }
 
     pub fn set_predict_offset(&mut self, diff_usec: i64) {
-        self.predict_offset = diff_usec as f64 / 1_000_000.0;
+        self.predict_offset.set(diff_usec as f64 / 1_000_000.0);
         self.flags.insert(ReceiverStatsFlags::HAS_PREDICT_OFFSET);
     }
 }
@@ -1,4 +1,5 @@
 use alsa::{Direction, PCM, pcm::{HwParams, Format, Access}, ValueOr};
+use bark_protocol::{AudioFormat, ChannelCount, SampleRate};
 use bark_protocol::time::SampleDuration;
 use thiserror::Error;
 
@@ -14,16 +15,19 @@ pub enum OpenError {
     InvalidBufferSize { min: i64, max: i64 },
 }
 
+/// Opens the device, returning it along with the audio format it actually
+/// runs at. The device picks the sample rate nearest to the one asked for,
+/// which it might not support exactly
 pub fn open_pcm(opt: &DeviceOpt, direction: Direction)
-    -> Result<PCM, OpenError>
+    -> Result<(PCM, AudioFormat), OpenError>
 {
     let device_name = opt.device.as_deref().unwrap_or("default");
     let pcm = PCM::new(device_name, direction, false)?;
 
     {
         let hwp = HwParams::any(&pcm)?;
-        hwp.set_channels(bark_protocol::CHANNELS.0.into())?;
-        hwp.set_rate(bark_protocol::SAMPLE_RATE.0, ValueOr::Nearest)?;
+        hwp.set_channels(opt.format.channels.into())?;
+        hwp.set_rate(opt.format.sample_rate.0, ValueOr::Nearest)?;
         hwp.set_format(Format::float())?;
         hwp.set_access(Access::RWInterleaved)?;
         set_period_size(&hwp, opt.period)?;
@@ -37,10 +41,22 @@ pub fn open_pcm(opt: &DeviceOpt, direction: Direction)
         swp.set_start_threshold(hwp.get_buffer_size()?)?;
     }
 
+    let format = {
+        let hwp = pcm.hw_params_current()?;
+        AudioFormat {
+            sample_rate: SampleRate(hwp.get_rate()?),
+            channels: ChannelCount(hwp.get_channels()?.try_into().unwrap()),
+        }
+    };
+
+    if format != opt.format {
+        log::warn!("device does not support {}, running at {} instead", opt.format, format);
+    }
+
     let (buffer, period) = pcm.get_params()?;
     log::info!("opened ALSA with buffer_size={buffer}, period_size={period}");
 
-    Ok(pcm)
+    Ok((pcm, format))
 }
 
 // period is the size of the discrete chunks of data that are sent to hardware
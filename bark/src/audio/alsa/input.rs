@@ -1,36 +1,50 @@
 use alsa::Direction;
 use alsa::pcm::PCM;
-use bark_core::audio::{Frame, self};
+use bark_core::audio::{FrameCount, Sample};
+use bark_protocol::AudioFormat;
 use bark_protocol::time::{Timestamp, SampleDuration};
 use nix::errno::Errno;
+use nix::time::ClockId;
 
 use crate::audio::config::DeviceOpt;
 use crate::audio::alsa::config::{self, OpenError};
-use crate::time;
+use crate::audio::alsa::timestamp;
 
 pub struct Input {
     pcm: PCM,
+    format: AudioFormat,
+    // clock ALSA timestamps the device on, if it does
+    clock: Option<ClockId>,
 }
 
 impl Input {
     pub fn new(opt: DeviceOpt) -> Result<Self, OpenError> {
-        let pcm = config::open_pcm(&opt, Direction::Capture)?;
-        Ok(Input { pcm })
+        let (pcm, format) = config::open_pcm(&opt, Direction::Capture)?;
+        let clock = timestamp::enable(&pcm, opt.timestamp)?;
+        Ok(Input { pcm, format, clock })
     }
 
-    pub fn read(&self, mut audio: &mut [Frame]) -> Result<Timestamp, alsa::Error> {
-        let now = Timestamp::from_micros_lossy(time::now());
-        let timestamp = now.saturating_sub(self.delay()?);
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Reads audio, returning when its first frame was captured
+    pub fn read(&self, mut audio: &mut [Sample]) -> Result<Timestamp, alsa::Error> {
+        let frames = audio.len() / usize::from(self.format.channels);
+        let read = SampleDuration::from_frames_at(frames as u64, self.format.sample_rate);
 
         while audio.len() > 0 {
             let n = self.read_partial(audio)?;
-            audio = &mut audio[n..];
+            audio = &mut audio[n.to_sample_count(self.format.channels)..];
         }
 
-        Ok(timestamp)
+        // everything still in the buffer and the hardware was captured
+        // after the audio we just read, so count back past both
+        let position = timestamp::position(&self.pcm, self.clock, self.format)?;
+        Ok(position.timestamp.saturating_sub(position.delay.add(read)))
     }
 
-    fn read_partial(&self, audio: &mut [Frame]) -> Result<usize, alsa::Error> {
+    fn read_partial(&self, audio: &mut [Sample]) -> Result<FrameCount, alsa::Error> {
         let io = unsafe {
             // the checked versions of this function call
             // snd_pcm_hw_params_current which mallocs under the hood
@@ -39,8 +53,8 @@ impl Input {
 
         loop {
             // try to write audio
-            let err = match io.readi(audio::as_interleaved_mut(audio)) {
-                Ok(n) => { return Ok(n) }
+            let err = match io.readi(audio) {
+                Ok(n) => { return Ok(FrameCount(n)) }
                 Err(e) => e,
             };
 
@@ -58,9 +72,4 @@ impl Input {
             }
         }
     }
-
-    fn delay(&self) -> Result<SampleDuration, alsa::Error> {
-        let frames = self.pcm.delay()?;
-        Ok(SampleDuration::from_frame_count(frames.try_into().unwrap()))
-    }
 }
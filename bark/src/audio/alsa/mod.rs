@@ -1,3 +1,4 @@
 pub mod config;
 pub mod input;
 pub mod output;
+pub mod timestamp;
@@ -1,32 +1,42 @@
 use alsa::Direction;
 use alsa::pcm::PCM;
-use bark_core::audio::{Frame, self};
-use bark_protocol::time::SampleDuration;
+use bark_core::audio::{FrameCount, Sample};
+use bark_protocol::AudioFormat;
 use nix::errno::Errno;
+use nix::time::ClockId;
 
 use crate::audio::config::DeviceOpt;
 use crate::audio::alsa::config::{self, OpenError};
+use crate::audio::alsa::timestamp::{self, Status};
 
 pub struct Output {
     pcm: PCM,
+    format: AudioFormat,
+    // clock ALSA timestamps the device on, if it does
+    clock: Option<ClockId>,
 }
 
 impl Output {
     pub fn new(opt: DeviceOpt) -> Result<Self, OpenError> {
-        let pcm = config::open_pcm(&opt, Direction::Playback)?;
-        Ok(Output { pcm })
+        let (pcm, format) = config::open_pcm(&opt, Direction::Playback)?;
+        let clock = timestamp::enable(&pcm, opt.timestamp)?;
+        Ok(Output { pcm, format, clock })
     }
 
-    pub fn write(&self, mut audio: &[Frame]) -> Result<(), alsa::Error> {
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    pub fn write(&self, mut audio: &[Sample]) -> Result<(), alsa::Error> {
         while audio.len() > 0 {
             let n = self.write_partial(audio)?;
-            audio = &audio[n..];
+            audio = &audio[n.to_sample_count(self.format.channels)..];
         }
 
         Ok(())
     }
 
-    fn write_partial(&self, audio: &[Frame]) -> Result<usize, alsa::Error> {
+    fn write_partial(&self, audio: &[Sample]) -> Result<FrameCount, alsa::Error> {
         let io = unsafe {
             // the checked versions of this function call
             // snd_pcm_hw_params_current which mallocs under the hood
@@ -35,8 +45,8 @@ impl Output {
 
         loop {
             // try to write audio
-            let err = match io.writei(audio::as_interleaved(audio)) {
-                Ok(n) => { return Ok(n) },
+            let err = match io.writei(audio) {
+                Ok(n) => { return Ok(FrameCount(n)) },
                 Err(e) => e,
             };
 
@@ -55,8 +65,9 @@ impl Output {
         }
     }
 
-    pub fn delay(&self) -> Result<SampleDuration, alsa::Error> {
-        let frames = self.pcm.delay()?;
-        Ok(SampleDuration::from_frame_count(frames.try_into().unwrap()))
+    /// Where the device is at, the next frame written plays `delay` after
+    /// `timestamp`
+    pub fn position(&self) -> Result<Status, alsa::Error> {
+        timestamp::position(&self.pcm, self.clock, self.format)
     }
 }
@@ -0,0 +1,76 @@
+use alsa::pcm::{PCM, State, TstampType};
+use bark_protocol::AudioFormat;
+use bark_protocol::time::{SampleDuration, Timestamp};
+use nix::sys::time::TimeSpec;
+use nix::time::ClockId;
+
+use crate::config::AudioTimestamp;
+use crate::time;
+
+/// Where the device is at, as of an instant timestamped by ALSA
+pub struct Status {
+    /// When ALSA last updated the device position, on bark's clock
+    pub timestamp: Timestamp,
+    /// Frames between the application and the device as of `timestamp`
+    pub delay: SampleDuration,
+}
+
+/// Has ALSA timestamp the device on the clock asked for, returning that
+/// clock, or None if we are to estimate timing ourselves
+pub fn enable(pcm: &PCM, timestamp: AudioTimestamp) -> Result<Option<ClockId>, alsa::Error> {
+    let (tstamp_type, clock) = match timestamp {
+        AudioTimestamp::Userspace => return Ok(None),
+        AudioTimestamp::MonotonicRaw => (TstampType::MonotonicRaw, ClockId::CLOCK_MONOTONIC_RAW),
+        AudioTimestamp::Monotonic => (TstampType::Monotonic, ClockId::CLOCK_MONOTONIC),
+        AudioTimestamp::Realtime => (TstampType::Gettimeofday, ClockId::CLOCK_REALTIME),
+    };
+
+    let swp = pcm.sw_params_current()?;
+    swp.set_tstamp_mode(true)?;
+    swp.set_tstamp_type(tstamp_type)?;
+    pcm.sw_params(&swp)?;
+
+    Ok(Some(clock))
+}
+
+/// Where the device is at. Uses ALSA's timestamp when there is one,
+/// otherwise reads our own clock alongside the device delay
+pub fn position(pcm: &PCM, clock: Option<ClockId>, format: AudioFormat) -> Result<Status, alsa::Error> {
+    if let Some(clock) = clock {
+        if let Some(status) = status(pcm, clock, format)? {
+            return Ok(status);
+        }
+    }
+
+    let timestamp = Timestamp::from_micros_lossy(time::now());
+    let frames = u64::try_from(pcm.delay()?).unwrap_or(0);
+    let delay = SampleDuration::from_frames_at(frames, format.sample_rate);
+
+    Ok(Status { timestamp, delay })
+}
+
+/// Reads the device status, timestamped on `clock`. The timestamp and delay
+/// are taken together by the driver, so unlike reading our own clock
+/// alongside the delay, they aren't thrown off by when we get scheduled.
+/// Returns None if the device isn't running or the driver doesn't timestamp
+fn status(pcm: &PCM, clock: ClockId, format: AudioFormat) -> Result<Option<Status>, alsa::Error> {
+    let status = pcm.status()?;
+
+    if status.get_state() != State::Running {
+        return Ok(None);
+    }
+
+    let htstamp = status.get_htstamp();
+
+    if htstamp.tv_sec == 0 && htstamp.tv_nsec == 0 {
+        return Ok(None);
+    }
+
+    let timestamp = time::from_clock(clock, TimeSpec::from(htstamp));
+    let timestamp = Timestamp::from_micros_lossy(timestamp);
+
+    let frames = u64::try_from(status.get_delay()).unwrap_or(0);
+    let delay = SampleDuration::from_frames_at(frames, format.sample_rate);
+
+    Ok(Some(Status { timestamp, delay }))
+}
@@ -1,5 +1,8 @@
+use bark_protocol::AudioFormat;
 use bark_protocol::time::SampleDuration;
 
+use crate::config::AudioTimestamp;
+
 pub const DEFAULT_PERIOD: SampleDuration = SampleDuration::from_frame_count(120);
 pub const DEFAULT_BUFFER: SampleDuration = SampleDuration::from_frame_count(360);
 
@@ -7,4 +10,6 @@ pub struct DeviceOpt {
     pub device: Option<String>,
     pub period: SampleDuration,
     pub buffer: SampleDuration,
+    pub format: AudioFormat,
+    pub timestamp: AudioTimestamp,
 }
@@ -1,7 +1,9 @@
-use bark_core::audio::Frame;
-use bark_protocol::time::{SampleDuration, Timestamp};
+use bark_core::audio::Sample;
+use bark_protocol::AudioFormat;
+use bark_protocol::time::Timestamp;
 use thiserror::Error;
 
+use self::alsa::timestamp::Status;
 use self::config::DeviceOpt;
 
 pub mod alsa;
@@ -30,7 +32,11 @@ impl Input {
         })
     }
 
-    pub fn read(&self, audio: &mut [Frame]) -> Result<Timestamp, Error> {
+    pub fn format(&self) -> AudioFormat {
+        self.alsa.format()
+    }
+
+    pub fn read(&self, audio: &mut [Sample]) -> Result<Timestamp, Error> {
         Ok(self.alsa.read(audio)?)
     }
 }
@@ -46,11 +52,15 @@ impl Output {
         })
     }
 
-    pub fn write(&self, audio: &[Frame]) -> Result<(), Error> {
+    pub fn format(&self) -> AudioFormat {
+        self.alsa.format()
+    }
+
+    pub fn write(&self, audio: &[Sample]) -> Result<(), Error> {
         Ok(self.alsa.write(audio)?)
     }
 
-    pub fn delay(&self) -> Result<SampleDuration, Error> {
-        Ok(self.alsa.delay()?)
+    pub fn position(&self) -> Result<Status, Error> {
+        Ok(self.alsa.position()?)
     }
 }
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use chacha20poly1305::{AeadInPlace, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use bark_protocol::types::{AuthTrailer, PacketHeader, SealTrailer};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const HEADER_SIZE: usize = std::mem::size_of::<PacketHeader>();
+const TRAILER_SIZE: usize = std::mem::size_of::<AuthTrailer>();
+const SEAL_SIZE: usize = std::mem::size_of::<SealTrailer>();
+const MAC_SIZE: usize = std::mem::size_of::<[u8; 16]>();
+
+// domain separation for deriving the encryption key from the pre-shared key
+const SEAL_KEY_LABEL: &[u8] = b"bark seal key";
+
+// how far behind the highest counter seen from a sender we will still accept
+// a packet. allows for some reordering on the network
+const REPLAY_WINDOW: u64 = 64;
+
+// how far the time a packet was signed at may be from our own wall clock,
+// either way. nodes' clocks need to agree to within this, and a recording
+// of a sender's traffic can't be replayed to a receiver any later than this
+// after it was sent
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("packet too short to contain authentication trailer")]
+    TooShort,
+    #[error("bad MAC")]
+    BadMac,
+    #[error("failed to decrypt packet")]
+    Decrypt,
+    #[error("replayed or stale packet (sender={sender:016x}, counter={counter})")]
+    Replayed { sender: u64, counter: u64 },
+    #[error("packet signed {skew:+.1}s from our clock, check clocks are in sync (sender={sender:016x})")]
+    Skewed { sender: u64, skew: f64 },
+}
+
+/// Authenticates outgoing packets and verifies incoming packets using a
+/// pre-shared key, optionally encrypting packet bodies too
+pub struct Authenticator {
+    mac: HmacSha256,
+    cipher: Option<XChaCha20Poly1305>,
+    sender: u64,
+    counter: AtomicU64,
+    replay: Mutex<HashMap<u64, ReplayWindow>>,
+}
+
+impl Authenticator {
+    pub fn new(key: &str) -> Self {
+        let mac = <HmacSha256 as Mac>::new_from_slice(key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+
+        Authenticator {
+            mac,
+            cipher: None,
+            sender: rand::random(),
+            counter: AtomicU64::new(0),
+            replay: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Enables sealing packet bodies, with an encryption key derived from
+    /// the pre-shared key
+    pub fn with_encryption(mut self) -> Self {
+        let mut kdf = self.mac.clone();
+        kdf.update(SEAL_KEY_LABEL);
+        let key = kdf.finalize().into_bytes();
+
+        self.cipher = Some(XChaCha20Poly1305::new(&key));
+        self
+    }
+
+    /// Returns a copy of the packet with an authentication trailer appended.
+    /// If `seal` is set, the packet body is also encrypted.
+    pub fn sign(&self, packet: &[u8], seal: bool) -> Vec<u8> {
+        self.sign_at(packet, seal, wall_clock())
+    }
+
+    fn sign_at(&self, packet: &[u8], seal: bool, time: u64) -> Vec<u8> {
+        let mut trailer = AuthTrailer {
+            sender: self.sender.into(),
+            counter: self.counter.fetch_add(1, Ordering::Relaxed).into(),
+            time: time.into(),
+            mac: [0; MAC_SIZE],
+        };
+
+        let mut signed = Vec::with_capacity(packet.len() + SEAL_SIZE + TRAILER_SIZE);
+        signed.extend_from_slice(packet);
+
+        if let (true, Some(cipher)) = (seal, &self.cipher) {
+            let (header, body) = signed.split_at_mut(HEADER_SIZE);
+
+            let tag = cipher.encrypt_in_place_detached(&nonce(&trailer), header, body)
+                .expect("packet too large to encrypt");
+
+            let seal = SealTrailer { tag: tag.into() };
+            signed.extend_from_slice(bytemuck::bytes_of(&seal));
+        }
+
+        let digest = self.digest(&signed, &trailer).finalize().into_bytes();
+        trailer.mac.copy_from_slice(&digest[..MAC_SIZE]);
+
+        signed.extend_from_slice(bytemuck::bytes_of(&trailer));
+        signed
+    }
+
+    /// Verifies the authentication trailer on a received packet and strips
+    /// it, leaving just the packet. If `sealed` is set, the packet body is
+    /// decrypted too.
+    pub fn verify(&self, buffer: &mut Vec<u8>, sealed: bool) -> Result<(), VerifyError> {
+        self.verify_at(buffer, sealed, wall_clock())
+    }
+
+    fn verify_at(&self, buffer: &mut Vec<u8>, sealed: bool, now: u64) -> Result<(), VerifyError> {
+        let packet_len = buffer.len().checked_sub(TRAILER_SIZE)
+            .ok_or(VerifyError::TooShort)?;
+
+        // trailer may not be aligned, packets can be any length:
+        let trailer: AuthTrailer = bytemuck::pod_read_unaligned(&buffer[packet_len..]);
+        let packet = &buffer[..packet_len];
+
+        self.digest(packet, &trailer)
+            .verify_truncated_left(&trailer.mac)
+            .map_err(|_| VerifyError::BadMac)?;
+
+        // a receiver which hasn't heard from this sender yet has no replay
+        // window to check against, the time the packet was signed at is
+        // all that stops it accepting a recording of old traffic
+        let skew = trailer.time.get().abs_diff(now);
+
+        if u128::from(skew) > MAX_CLOCK_SKEW.as_micros() {
+            let skew = trailer.time.get() as f64 - now as f64;
+
+            return Err(VerifyError::Skewed {
+                sender: trailer.sender.get(),
+                skew: skew / 1_000_000.0,
+            });
+        }
+
+        // only check for replay after verifying the MAC, so that forged
+        // packets can't advance the replay window
+        let mut replay = self.replay.lock().unwrap();
+        let window = replay.entry(trailer.sender.get()).or_default();
+
+        if !window.accept(trailer.counter.get()) {
+            return Err(VerifyError::Replayed {
+                sender: trailer.sender.get(),
+                counter: trailer.counter.get(),
+            });
+        }
+
+        drop(replay);
+
+        buffer.truncate(packet_len);
+
+        if let (true, Some(cipher)) = (sealed, &self.cipher) {
+            let body_len = packet_len.checked_sub(SEAL_SIZE)
+                .filter(|len| *len >= HEADER_SIZE)
+                .ok_or(VerifyError::TooShort)?;
+
+            let seal: SealTrailer = bytemuck::pod_read_unaligned(&buffer[body_len..]);
+            buffer.truncate(body_len);
+
+            let (header, body) = buffer.split_at_mut(HEADER_SIZE);
+
+            cipher.decrypt_in_place_detached(&nonce(&trailer), header, body, Tag::from_slice(&seal.tag))
+                .map_err(|_| VerifyError::Decrypt)?;
+        }
+
+        Ok(())
+    }
+
+    fn digest(&self, packet: &[u8], trailer: &AuthTrailer) -> HmacSha256 {
+        let mut mac = self.mac.clone();
+        mac.update(packet);
+        mac.update(&trailer.sender.get().to_le_bytes());
+        mac.update(&trailer.counter.get().to_le_bytes());
+        mac.update(&trailer.time.get().to_le_bytes());
+        mac
+    }
+}
+
+/// Microseconds since the unix epoch
+fn wall_clock() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    u64::try_from(since_epoch.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Every packet a node sends has a unique sender + counter pair, which makes
+/// it suitable for use as a nonce
+fn nonce(trailer: &AuthTrailer) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[0..8].copy_from_slice(&trailer.sender.get().to_le_bytes());
+    nonce[8..16].copy_from_slice(&trailer.counter.get().to_le_bytes());
+    nonce
+}
+
+/// Sliding window replay protection, as in IPsec. Tracks the highest counter
+/// seen from a sender and a bitmap of which of the preceding counters have
+/// been seen.
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, counter: u64) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(counter);
+            self.seen = 1;
+            return true;
+        };
+
+        if counter > highest {
+            let shift = counter - highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = Some(counter);
+            return true;
+        }
+
+        let age = highest - counter;
+
+        if age >= REPLAY_WINDOW {
+            // too old to tell whether we've seen it, reject
+            return false;
+        }
+
+        let bit = 1 << age;
+
+        if self.seen & bit != 0 {
+            return false;
+        }
+
+        self.seen |= bit;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000_000;
+
+    fn accepts(window: &mut ReplayWindow, counters: impl IntoIterator<Item = u64>) -> Vec<bool> {
+        counters.into_iter().map(|counter| window.accept(counter)).collect()
+    }
+
+    #[test]
+    fn accepts_counter_zero_once() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(accepts(&mut window, [0, 0, 1, 0]), [true, false, true, false]);
+    }
+
+    #[test]
+    fn accepts_counter_zero_behind_first_seen() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(accepts(&mut window, [5, 0, 0]), [true, true, false]);
+    }
+
+    #[test]
+    fn jump_past_window_forgets_everything_before_it() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(accepts(&mut window, [0, 1, 100]), [true, true, true]);
+
+        // 36 is exactly a window behind 100, 37 is just inside it
+        assert_eq!(accepts(&mut window, [1, 36, 37, 37]), [false, false, true, false]);
+    }
+
+    #[test]
+    fn duplicate_at_window_boundary() {
+        let mut window = ReplayWindow::default();
+        assert!(accepts(&mut window, 0..REPLAY_WINDOW).into_iter().all(|accepted| accepted));
+
+        // counter 0 is the oldest the window still remembers
+        assert!(!window.accept(0));
+
+        // and moving the window on one forgets it entirely
+        assert!(window.accept(REPLAY_WINDOW));
+        assert!(!window.accept(0));
+        assert!(!window.accept(1));
+    }
+
+    #[test]
+    fn verifies_signed_packet_once() {
+        let auth = Authenticator::new("key");
+        let packet = vec![0x55; 64];
+
+        let mut buffer = auth.sign_at(&packet, false, NOW);
+        let mut replayed = buffer.clone();
+
+        assert!(auth.verify_at(&mut buffer, false, NOW).is_ok());
+        assert_eq!(buffer, packet);
+
+        assert!(matches!(auth.verify_at(&mut replayed, false, NOW), Err(VerifyError::Replayed { .. })));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let auth = Authenticator::new("key");
+        let other = Authenticator::new("other key");
+
+        let mut buffer = other.sign_at(&[0x55; 64], false, NOW);
+        assert!(matches!(auth.verify_at(&mut buffer, false, NOW), Err(VerifyError::BadMac)));
+    }
+
+    #[test]
+    fn rejects_packets_signed_too_far_from_our_clock() {
+        let auth = Authenticator::new("key");
+        let skew = MAX_CLOCK_SKEW.as_micros() as u64;
+
+        // a receiver which has never seen the sender, as after a restart,
+        // still rejects a recording of its traffic
+        let mut old = auth.sign_at(&[0x55; 64], false, NOW - skew - 1);
+        assert!(matches!(auth.verify_at(&mut old, false, NOW), Err(VerifyError::Skewed { .. })));
+
+        let mut ahead = auth.sign_at(&[0x55; 64], false, NOW + skew + 1);
+        assert!(matches!(auth.verify_at(&mut ahead, false, NOW), Err(VerifyError::Skewed { .. })));
+
+        let mut edge = auth.sign_at(&[0x55; 64], false, NOW - skew);
+        assert!(auth.verify_at(&mut edge, false, NOW).is_ok());
+    }
+
+    #[test]
+    fn skewed_packet_leaves_replay_window_alone() {
+        let auth = Authenticator::new("key");
+        let skew = MAX_CLOCK_SKEW.as_micros() as u64;
+
+        let mut fresh = auth.sign_at(&[0x55; 64], false, NOW);
+
+        // counter a whole window ahead of the fresh packet's
+        auth.counter.fetch_add(REPLAY_WINDOW, Ordering::Relaxed);
+        let mut stale = auth.sign_at(&[0x55; 64], false, NOW - skew - 1);
+
+        assert!(auth.verify_at(&mut stale, false, NOW).is_err());
+        assert!(auth.verify_at(&mut fresh, false, NOW).is_ok());
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let auth = Authenticator::new("key").with_encryption();
+        let packet = (0..64).collect::<Vec<u8>>();
+
+        let mut buffer = auth.sign_at(&packet, true, NOW);
+        assert_ne!(&buffer[HEADER_SIZE..packet.len()], &packet[HEADER_SIZE..]);
+
+        assert!(auth.verify_at(&mut buffer, true, NOW).is_ok());
+        assert_eq!(buffer, packet);
+    }
+}
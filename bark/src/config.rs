@@ -1,7 +1,7 @@
 use std::env;
 use std::fmt::Display;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use serde::Deserialize;
@@ -10,6 +10,15 @@ use thiserror::Error;
 #[derive(Deserialize)]
 pub struct Config {
     multicast: Option<SocketAddr>,
+    interface: Option<String>,
+    #[serde(default)]
+    unicast: Vec<SocketAddr>,
+    listen: Option<SocketAddr>,
+    zone: Option<String>,
+    takeover: Option<Takeover>,
+    key: Option<String>,
+    encrypt: Option<Encrypt>,
+    timestamps: Option<Timestamps>,
     #[serde(default)]
     source: Source,
     #[serde(default)]
@@ -22,6 +31,11 @@ pub struct Source {
     input: Device,
     delay_ms: Option<u64>,
     format: Option<Format>,
+    name: Option<String>,
+    now_playing: Option<PathBuf>,
+    fec_group: Option<u16>,
+    priority: Option<u16>,
+    time_padding: Option<TimePadding>,
 }
 
 #[derive(Deserialize)]
@@ -62,10 +76,201 @@ impl Display for Format {
     }
 }
 
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encrypt {
+    /// Encrypt audio packets only
+    Audio,
+    /// Encrypt audio, time, stats reply, metadata, control, and volume packets
+    All,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown encryption mode")]
+pub struct UnknownEncrypt;
+
+impl FromStr for Encrypt {
+    type Err = UnknownEncrypt;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "audio" => Ok(Encrypt::Audio),
+            "all" => Ok(Encrypt::All),
+            _ => Err(UnknownEncrypt),
+        }
+    }
+}
+
+impl Display for Encrypt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encrypt::Audio => write!(f, "audio"),
+            Encrypt::All => write!(f, "all"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Takeover {
+    /// The most recently started stream wins
+    Newest,
+    /// The stream with the highest priority wins, newest first among equals
+    Priority,
+    /// The stream already playing keeps playing until it ends
+    First,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown takeover policy")]
+pub struct UnknownTakeover;
+
+impl FromStr for Takeover {
+    type Err = UnknownTakeover;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest" => Ok(Takeover::Newest),
+            "priority" => Ok(Takeover::Priority),
+            "first" => Ok(Takeover::First),
+            _ => Err(UnknownTakeover),
+        }
+    }
+}
+
+impl Display for Takeover {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Takeover::Newest => write!(f, "newest"),
+            Takeover::Priority => write!(f, "priority"),
+            Takeover::First => write!(f, "first"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimePadding {
+    /// Send time packets at their natural size
+    None,
+    /// Pad time packets to the size of the most recent audio packet
+    Audio,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown time padding")]
+pub struct UnknownTimePadding;
+
+impl FromStr for TimePadding {
+    type Err = UnknownTimePadding;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TimePadding::None),
+            "audio" => Ok(TimePadding::Audio),
+            _ => Err(UnknownTimePadding),
+        }
+    }
+}
+
+impl Display for TimePadding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimePadding::None => write!(f, "none"),
+            TimePadding::Audio => write!(f, "audio"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Timestamps {
+    /// Timestamp packets in userspace once they have been received
+    Userspace,
+    /// Use the kernel's timestamps of when packets arrived and departed
+    Kernel,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown timestamp source")]
+pub struct UnknownTimestamps;
+
+impl FromStr for Timestamps {
+    type Err = UnknownTimestamps;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "userspace" => Ok(Timestamps::Userspace),
+            "kernel" => Ok(Timestamps::Kernel),
+            _ => Err(UnknownTimestamps),
+        }
+    }
+}
+
+impl Display for Timestamps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timestamps::Userspace => write!(f, "userspace"),
+            Timestamps::Kernel => write!(f, "kernel"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioTimestamp {
+    /// Estimate when audio was captured or will play from the device delay
+    /// and our own clock, read when we get round to it
+    Userspace,
+    /// Have ALSA timestamp the device on CLOCK_MONOTONIC_RAW, bark's own
+    /// clock
+    MonotonicRaw,
+    /// Have ALSA timestamp the device on CLOCK_MONOTONIC
+    Monotonic,
+    /// Have ALSA timestamp the device on CLOCK_REALTIME
+    Realtime,
+}
+
+#[derive(Debug, Error)]
+#[error("unknown audio timestamp type")]
+pub struct UnknownAudioTimestamp;
+
+impl FromStr for AudioTimestamp {
+    type Err = UnknownAudioTimestamp;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "userspace" => Ok(AudioTimestamp::Userspace),
+            "monotonic-raw" => Ok(AudioTimestamp::MonotonicRaw),
+            "monotonic" => Ok(AudioTimestamp::Monotonic),
+            "realtime" => Ok(AudioTimestamp::Realtime),
+            _ => Err(UnknownAudioTimestamp),
+        }
+    }
+}
+
+impl Display for AudioTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioTimestamp::Userspace => write!(f, "userspace"),
+            AudioTimestamp::MonotonicRaw => write!(f, "monotonic-raw"),
+            AudioTimestamp::Monotonic => write!(f, "monotonic"),
+            AudioTimestamp::Realtime => write!(f, "realtime"),
+        }
+    }
+}
+
 #[derive(Deserialize, Default)]
 pub struct Receive {
     #[serde(default)]
     output: Device,
+    stream_timeout: Option<u64>,
+    rate_kp: Option<f64>,
+    rate_ki: Option<f64>,
+    max_rate_adjust: Option<f64>,
+    slew_start_ms: Option<f64>,
+    slew_stop_ms: Option<f64>,
+    resync_threshold_ms: Option<f64>,
 }
 
 #[derive(Deserialize, Default)]
@@ -73,6 +278,9 @@ pub struct Device {
     device: Option<String>,
     period: Option<u64>,
     buffer: Option<u64>,
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+    timestamp: Option<AudioTimestamp>,
 }
 
 fn set_env<T: ToString>(name: &str, value: T) {
@@ -85,16 +293,52 @@ fn set_env_option<T: ToString>(name: &str, value: Option<T>) {
     }
 }
 
+fn set_env_list<T: ToString>(name: &str, values: &[T]) {
+    if !values.is_empty() {
+        let values = values.iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>();
+
+        set_env(name, values.join(","));
+    }
+}
+
 pub fn load_into_env(config: &Config) {
     set_env_option("BARK_MULTICAST", config.multicast);
+    set_env_option("BARK_INTERFACE", config.interface.as_ref());
+    set_env_list("BARK_UNICAST", &config.unicast);
+    set_env_option("BARK_LISTEN", config.listen);
+    set_env_option("BARK_ZONE", config.zone.as_ref());
+    set_env_option("BARK_TAKEOVER", config.takeover);
+    set_env_option("BARK_KEY", config.key.as_ref());
+    set_env_option("BARK_ENCRYPT", config.encrypt);
+    set_env_option("BARK_TIMESTAMPS", config.timestamps);
     set_env_option("BARK_SOURCE_DELAY_MS", config.source.delay_ms);
     set_env_option("BARK_SOURCE_INPUT_DEVICE", config.source.input.device.as_ref());
     set_env_option("BARK_SOURCE_INPUT_PERIOD", config.source.input.period);
     set_env_option("BARK_SOURCE_INPUT_BUFFER", config.source.input.buffer);
+    set_env_option("BARK_SOURCE_INPUT_SAMPLE_RATE", config.source.input.sample_rate);
+    set_env_option("BARK_SOURCE_INPUT_CHANNELS", config.source.input.channels);
+    set_env_option("BARK_SOURCE_INPUT_TIMESTAMP", config.source.input.timestamp);
     set_env_option("BARK_SOURCE_FORMAT", config.source.format.as_ref());
+    set_env_option("BARK_SOURCE_NAME", config.source.name.as_ref());
+    set_env_option("BARK_SOURCE_FEC_GROUP", config.source.fec_group);
+    set_env_option("BARK_SOURCE_PRIORITY", config.source.priority);
+    set_env_option("BARK_SOURCE_TIME_PADDING", config.source.time_padding);
+    set_env_option("BARK_SOURCE_NOW_PLAYING", config.source.now_playing.as_ref().map(|path| path.display()));
     set_env_option("BARK_RECEIVE_OUTPUT_DEVICE", config.receive.output.device.as_ref());
     set_env_option("BARK_RECEIVE_OUTPUT_PERIOD", config.receive.output.period);
     set_env_option("BARK_RECEIVE_OUTPUT_BUFFER", config.receive.output.buffer);
+    set_env_option("BARK_RECEIVE_OUTPUT_SAMPLE_RATE", config.receive.output.sample_rate);
+    set_env_option("BARK_RECEIVE_OUTPUT_CHANNELS", config.receive.output.channels);
+    set_env_option("BARK_RECEIVE_OUTPUT_TIMESTAMP", config.receive.output.timestamp);
+    set_env_option("BARK_RECEIVE_STREAM_TIMEOUT", config.receive.stream_timeout);
+    set_env_option("BARK_RECEIVE_RATE_KP", config.receive.rate_kp);
+    set_env_option("BARK_RECEIVE_RATE_KI", config.receive.rate_ki);
+    set_env_option("BARK_RECEIVE_MAX_RATE_ADJUST", config.receive.max_rate_adjust);
+    set_env_option("BARK_RECEIVE_SLEW_START_MS", config.receive.slew_start_ms);
+    set_env_option("BARK_RECEIVE_SLEW_STOP_MS", config.receive.slew_stop_ms);
+    set_env_option("BARK_RECEIVE_RESYNC_THRESHOLD_MS", config.receive.resync_threshold_ms);
 }
 
 fn load_file(path: &Path) -> Option<Config> {
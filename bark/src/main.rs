@@ -1,11 +1,16 @@
 mod audio;
+mod auth;
 mod config;
+mod now_playing;
 mod receive;
 mod socket;
 mod stats;
 mod stream;
+mod streams;
+mod takeover;
 mod thread;
 mod time;
+mod volume;
 
 use std::process::ExitCode;
 
@@ -19,6 +24,8 @@ enum Opt {
     Stream(stream::StreamOpt),
     Receive(receive::ReceiveOpt),
     Stats(stats::StatsOpt),
+    Streams(streams::StreamsOpt),
+    Volume(volume::VolumeOpt),
 }
 
 #[derive(Debug, Error)]
@@ -29,8 +36,18 @@ pub enum RunError {
     OpenAudioDevice(#[from] audio::OpenError),
     #[error("receiving from network: {0}")]
     Receive(std::io::Error),
+    #[error("sending to network: {0}")]
+    Send(std::io::Error),
     #[error("opening encoder: {0}")]
     OpenEncoder(#[from] bark_core::encode::NewEncoderError),
+    #[error("opening now playing file: {0}")]
+    OpenNowPlaying(std::io::Error),
+    #[error("fec group must be between 2 and {} packets, got {0}", bark_core::consts::MAX_PARITY_GROUP)]
+    InvalidFecGroup(u16),
+    #[error("setting up signal handling: {0}")]
+    Signal(nix::Error),
+    #[error("volume must be between 0 and 100, got {0}")]
+    InvalidVolume(u8),
 }
 
 fn main() -> Result<(), ExitCode> {
@@ -46,6 +63,8 @@ fn main() -> Result<(), ExitCode> {
         Opt::Stream(opt) => stream::run(opt),
         Opt::Receive(opt) => receive::run(opt),
         Opt::Stats(opt) => stats::run(opt),
+        Opt::Streams(opt) => streams::run(opt),
+        Opt::Volume(opt) => volume::run(opt),
     };
 
     result.map_err(|err| {
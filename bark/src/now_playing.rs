@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Source of the "now playing" string sent in stream metadata.
+///
+/// If the path names a FIFO, each line written to it replaces the current
+/// title. Otherwise the path is treated as a regular file and its contents
+/// are re-read every time the title is requested.
+pub enum NowPlaying {
+    File(PathBuf),
+    Fifo(Arc<Mutex<String>>),
+}
+
+impl NowPlaying {
+    pub fn open(path: PathBuf) -> Result<Self, io::Error> {
+        let metadata = std::fs::metadata(&path)?;
+
+        if !metadata.file_type().is_fifo() {
+            return Ok(NowPlaying::File(path));
+        }
+
+        let title = Arc::new(Mutex::new(String::new()));
+
+        std::thread::spawn({
+            let title = title.clone();
+            move || {
+                crate::thread::set_name("bark/now-playing");
+
+                loop {
+                    if let Err(e) = read_fifo(&path, &title) {
+                        log::error!("error reading now playing from {}: {e}", path.display());
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(NowPlaying::Fifo(title))
+    }
+
+    pub fn get(&self) -> String {
+        match self {
+            NowPlaying::File(path) => {
+                std::fs::read_to_string(path)
+                    .map(|contents| contents.trim().to_owned())
+                    .unwrap_or_default()
+            }
+            NowPlaying::Fifo(title) => {
+                title.lock().unwrap().clone()
+            }
+        }
+    }
+}
+
+fn read_fifo(path: &Path, title: &Mutex<String>) -> Result<(), io::Error> {
+    // blocks until a writer opens the other end, and reaches EOF once all
+    // writers have closed it, at which point our caller reopens
+    let fifo = BufReader::new(File::open(path)?);
+
+    for line in fifo.lines() {
+        *title.lock().unwrap() = line?.trim().to_owned();
+    }
+
+    Ok(())
+}
@@ -1,56 +1,215 @@
 use std::array;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use bark_core::audio::Frame;
+use bark_core::audio::Sample;
+use bark_core::consts::MAX_OUTPUT_FRAMES_PER_PACKET;
+use bark_core::receive::clock::ClockModel;
+use bark_core::receive::gain::{self, Gain};
 use bark_core::receive::pipeline::Pipeline;
-use bark_core::receive::timing::Timing;
+use bark_core::receive::rtt::RttFilter;
+use bark_core::receive::timing::{RateParams, Timing};
 use bytemuck::Zeroable;
 use structopt::StructOpt;
 
-use bark_core::receive::queue::PacketQueue;
+use bark_core::receive::queue::{PacketQueue, RetransmitRequests};
 
-use bark_protocol::FRAMES_PER_PACKET;
+use bark_protocol::{AudioFormat, ChannelCount, SampleRate, FRAMES_PER_PACKET};
 use bark_protocol::time::{Timestamp, SampleDuration, TimestampDelta, ClockDelta};
-use bark_protocol::types::{SessionId, ReceiverId, TimePhase, AudioPacketHeader};
+use bark_protocol::types::{SessionId, ReceiverId, TimePhase, AudioPacketHeader, ControlAction, MetadataPacket, ZoneId};
+use bark_protocol::types::stats::clock::ClockStats;
 use bark_protocol::types::stats::receiver::{ReceiverStats, StreamStatus};
-use bark_protocol::packet::{Audio, Time, PacketKind, StatsReply};
+use bark_protocol::packet::{Audio, Control, Metadata, Parity, Retransmit, Time, PacketKind, StatsReply};
 
 use crate::audio::config::{DEFAULT_PERIOD, DEFAULT_BUFFER, DeviceOpt};
 use crate::audio::Output;
-use crate::socket::{ProtocolSocket, Socket, SocketOpt};
-use crate::{time, stats, thread};
+use crate::socket::{PeerId, ProtocolSocket, SocketOpt};
+use crate::config::{self, Takeover};
+use crate::{time, stats, takeover, thread};
 use crate::RunError;
 
+/// Lower bound on how long we wait before asking again for a packet we have
+/// already requested a retransmit of
+const MIN_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long it takes to fade out when a stream pauses, and back in when it
+/// resumes
+const FADE_DURATION: Duration = Duration::from_millis(10);
+
+/// How long it takes to ramp to a new volume
+const VOLUME_RAMP_DURATION: Duration = Duration::from_millis(50);
+
+/// How often we ask the source for a time exchange while our clock estimate
+/// for a new stream converges, much faster than the source's own cadence
+const TIME_REQUEST_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Most time exchanges we ask for per stream. If the estimate still hasn't
+/// converged by then, the network is too noisy for more to help much, and
+/// we carry on with the source's own cadence
+const MAX_TIME_REQUESTS: usize = 50;
+
 pub struct Receiver {
     stats: ReceiverStats,
+    clock_stats: ClockStats,
     stream: Option<Stream>,
+    output: AudioFormat,
+    takeover: Takeover,
+    stream_timeout: Duration,
+    rate: RateParams,
+    volume: u8,
+    muted: bool,
+    volume_gain: Gain,
 }
 
 struct Stream {
     sid: SessionId,
+    header: AudioPacketHeader,
+    last_audio: Instant,
+    format: AudioFormat,
     latency: Aggregate<Duration>,
-    clock_delta: Aggregate<ClockDelta>,
+    rtt: RttFilter,
+    clock: ClockModel,
     queue: PacketQueue,
     pipeline: Pipeline,
+    metadata: Option<MetadataPacket>,
+    retransmit: RetransmitRequests,
+    pause: Option<Pause>,
+    // set once the source has announced the end of the stream, which then
+    // plays out up to the seq in `pause`
+    ending: bool,
+    gain: Gain,
+    // peer the stream's audio comes from, which we ask for time exchanges
+    source: Option<PeerId>,
+    // when clock sync with the source began, and how many time exchanges
+    // we have asked for since
+    clock_began: Instant,
+    time_requests: usize,
+    clock_converged: bool,
+}
+
+enum Pause {
+    /// Source has paused, play out audio up to `seq` then go silent
+    Pausing { seq: u64 },
+    /// Source has resumed, stay silent until its first packet is due
+    Resuming,
 }
 
 impl Stream {
-    pub fn new(header: &AudioPacketHeader) -> Self {
+    pub fn new(header: &AudioPacketHeader, output: AudioFormat, rate: RateParams) -> Self {
         let queue = PacketQueue::new(header);
 
 
         Stream {
             sid: header.sid,
+            header: *header,
+            last_audio: Instant::now(),
+            format: header.audio_format(),
             latency: Aggregate::new(),
-            clock_delta: Aggregate::new(),
+            rtt: RttFilter::new(),
+            clock: ClockModel::new(),
             queue,
-            pipeline: Pipeline::new(header),
+            pipeline: Pipeline::new(header, output, rate),
+            metadata: None,
+            retransmit: RetransmitRequests::new(),
+            pause: None,
+            ending: false,
+            gain: Gain::new(1.0),
+            source: None,
+            clock_began: Instant::now(),
+            time_requests: 0,
+            clock_converged: false,
+        }
+    }
+
+    fn resume(&mut self, output: AudioFormat) {
+        if self.ending {
+            return;
+        }
+
+        match self.pause {
+            Some(Pause::Pausing { seq }) if self.queue.head_seq() < seq => {
+                // resumed before we got as far as pausing, carry on playing
+                log::info!("stream resumed");
+                self.pause = None;
+                self.gain.ramp_to(1.0, frames_in(FADE_DURATION, output));
+            }
+            Some(Pause::Pausing { .. }) => {
+                log::info!("stream resumed");
+                self.pause = Some(Pause::Resuming);
+            }
+            Some(Pause::Resuming) | None => {}
         }
     }
 
+    /// Whether output should be held silent for the packet due at `pts`.
+    /// While held we don't consume from the queue at all, so that when the
+    /// stream resumes it carries on where it left off, without having to
+    /// buffer up from scratch
+    fn hold(&mut self, pts: Timestamp, output: AudioFormat) -> bool {
+        match self.pause {
+            None => false,
+            Some(Pause::Pausing { seq }) => {
+                let remaining = seq.saturating_sub(self.queue.head_seq());
+
+                if remaining == 0 {
+                    self.gain.ramp_to(0.0, 0);
+                    return true;
+                }
+
+                // start fading out in time to reach silence at the pause
+                let until_pause = self.format.packet_duration().to_std_duration_lossy()
+                    * u32::try_from(remaining).unwrap_or(u32::MAX);
+
+                if until_pause <= FADE_DURATION && self.gain.target() != 0.0 {
+                    self.gain.ramp_to(0.0, frames_in(until_pause, output));
+                }
+
+                false
+            }
+            Some(Pause::Resuming) => {
+                let due = match self.queue.front() {
+                    Some(packet) => {
+                        let packet_pts = Timestamp::from_micros_lossy(packet.header().pts);
+                        self.adjust_pts(packet_pts)
+                            .map(|packet_pts| packet_pts <= pts)
+                            .unwrap_or(true)
+                    }
+                    // first packet after the pause was lost, carry on
+                    // once anything after it has arrived
+                    None => self.queue.len() > 0,
+                };
+
+                if !due {
+                    return true;
+                }
+
+                self.pause = None;
+                self.gain.ramp_to(1.0, frames_in(FADE_DURATION, output));
+                false
+            }
+        }
+    }
+
+    /// Carries on this stream from a different source after a standby has
+    /// failed over. The queue and pipeline are kept, but the new source's
+    /// clock has nothing to do with the old one, so clock sync starts over
+    pub fn failover(&mut self, header: &AudioPacketHeader) {
+        self.sid = header.sid;
+        self.header = *header;
+        self.latency = Aggregate::new();
+        self.rtt = RttFilter::new();
+        self.clock = ClockModel::new();
+        self.source = None;
+        self.clock_began = Instant::now();
+        self.time_requests = 0;
+        self.clock_converged = false;
+        self.retransmit.clear();
+    }
+
     pub fn adjust_pts(&self, pts: Timestamp) -> Option<Timestamp> {
-        self.clock_delta.median().map(|delta| {
+        let stream_usec = pts.to_micros_lossy().get();
+
+        self.clock.predict(stream_usec).map(|delta| {
             pts.adjust(TimestampDelta::from_clock_delta_lossy(delta))
         })
     }
@@ -61,17 +220,46 @@ impl Stream {
 }
 
 impl Receiver {
-    pub fn new() -> Self {
+    pub fn new(output: AudioFormat, takeover: Takeover, stream_timeout: Duration, rate: RateParams) -> Self {
+        let mut stats = ReceiverStats::new();
+        stats.set_stream(StreamStatus::Idle);
+        stats.set_volume(100, false);
+
         Receiver {
             stream: None,
-            stats: ReceiverStats::new(),
+            stats,
+            clock_stats: ClockStats::new(),
+            output,
+            takeover,
+            stream_timeout,
+            rate,
+            volume: 100,
+            muted: false,
+            volume_gain: Gain::new(1.0),
         }
     }
 
+    /// Changes output volume and mute state, ramping smoothly to the new
+    /// level. None leaves the respective setting as it is
+    pub fn set_volume(&mut self, volume: Option<u8>, mute: Option<bool>) {
+        self.volume = volume.map(|volume| volume.min(100)).unwrap_or(self.volume);
+        self.muted = mute.unwrap_or(self.muted);
+
+        log::info!("volume set to {}%{}", self.volume, if self.muted { " (muted)" } else { "" });
+
+        let target = if self.muted { 0.0 } else { gain::volume_gain(self.volume) };
+        self.volume_gain.ramp_to(target, frames_in(VOLUME_RAMP_DURATION, self.output));
+        self.stats.set_volume(self.volume, self.muted);
+    }
+
     pub fn stats(&self) -> &ReceiverStats {
         &self.stats
     }
 
+    pub fn clock_stats(&self) -> &ClockStats {
+        &self.clock_stats
+    }
+
     pub fn current_session(&self) -> Option<SessionId> {
         self.stream.as_ref().map(|s| s.sid)
     }
@@ -87,14 +275,20 @@ impl Receiver {
             return;
         }
 
-        let stream_1_usec = packet.data().stream_1.0;
-        let stream_3_usec = packet.data().stream_3.0;
+        let stream_1_usec = packet.data().stream_1.get();
+        let stream_3_usec = packet.data().stream_3.get();
 
         let Some(rtt_usec) = stream_3_usec.checked_sub(stream_1_usec) else {
             // invalid packet, ignore
             return;
         };
 
+        if !stream.rtt.observe(Duration::from_micros(rtt_usec)) {
+            // held up on the way, measurements from it are skewed
+            self.clock_stats.add_rejected();
+            return;
+        }
+
         let network_latency = Duration::from_micros(rtt_usec / 2);
         stream.latency.observe(network_latency);
 
@@ -102,53 +296,281 @@ impl Receiver {
             self.stats.set_network_latency(latency);
         }
 
+        // the delta is measured as of halfway through the round trip
+        let measured_usec = stream_1_usec + rtt_usec / 2;
+
         let clock_delta = ClockDelta::from_time_packet(&packet);
-        stream.clock_delta.observe(clock_delta);
+        stream.clock.observe(measured_usec, clock_delta);
+
+        if let Some(drift) = stream.clock.drift_ppm() {
+            self.clock_stats.set_drift(drift);
+        }
+
+        if !stream.clock_converged && stream.clock.is_converged() {
+            let elapsed = stream.clock_began.elapsed();
+            log::info!("clock sync converged in {}ms", elapsed.as_millis());
+            stream.clock_converged = true;
+            self.clock_stats.set_converge(elapsed);
+        }
+    }
+
+    /// A time packet asking the stream source to start an exchange with us
+    /// right away, along with the peer to send it to. Only while the clock
+    /// estimate for a new stream is still converging, so that it converges
+    /// in a fraction of the time the source's own cadence would take
+    pub fn time_request(&mut self, rid: ReceiverId) -> Option<(Time, PeerId)> {
+        let stream = self.stream.as_mut()?;
+
+        if stream.clock_converged || stream.time_requests >= MAX_TIME_REQUESTS {
+            return None;
+        }
+
+        let peer = stream.source?;
+        stream.time_requests += 1;
+
+        let mut time = Time::allocate()
+            .expect("allocate Time packet");
+
+        let data = time.data_mut();
+        data.sid = stream.sid;
+        data.rid = rid;
+        data.zone = stream.header.zone;
+
+        Some((time, peer))
+    }
+
+    pub fn receive_metadata(&mut self, packet: Metadata) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+
+        let metadata = packet.data();
+
+        if stream.sid != metadata.sid {
+            return;
+        }
+
+        match &stream.metadata {
+            None => {
+                log::info!("stream sid={} is \"{}\": codec={} ({}), delay={}ms",
+                    metadata.sid.get(),
+                    metadata.name(),
+                    metadata.format,
+                    metadata.audio_format(),
+                    metadata.delay.get() / 1000);
+
+                if !metadata.now_playing().is_empty() {
+                    log::info!("now playing: {}", metadata.now_playing());
+                }
+            }
+            Some(prev) => {
+                if prev.now_playing() != metadata.now_playing() {
+                    log::info!("now playing: {}", metadata.now_playing());
+                }
+            }
+        }
+
+        stream.metadata = Some(*metadata);
     }
 
-    fn prepare_stream(&mut self, header: &AudioPacketHeader) -> &mut Stream {
+    /// Returns the stream this packet belongs to, switching streams first
+    /// if the takeover policy says it should replace the current one. Returns
+    /// None if the packet is from a stream we are not playing
+    fn prepare_stream(&mut self, header: &AudioPacketHeader) -> Option<&mut Stream> {
         let new_stream = match &self.stream {
-            Some(stream) => stream.sid < header.sid,
+            Some(stream) if stream.header.stream == header.stream => false,
+            Some(stream) if stream.last_audio.elapsed() >= takeover::STREAM_TIMEOUT => {
+                log::info!("stream sid={} has ended", stream.sid.get());
+                true
+            }
+            Some(stream) => takeover::takes_over(self.takeover, &stream.header, header),
             None => true,
         };
 
         if new_stream {
             // new stream is taking over! switch over to it
-            log::info!("new stream beginning: sid={}", header.sid.0);
-            self.stream = Some(Stream::new(header));
+            log::info!("new stream beginning: sid={}, priority={}, format={}",
+                header.sid.get(), header.priority.get(), header.audio_format());
+            self.stream = Some(Stream::new(header, self.output, self.rate));
             self.stats.clear();
+            self.clock_stats.clear();
         }
 
-        self.stream.as_mut().unwrap()
+        let stream = self.stream.as_mut()
+            .filter(|stream| stream.header.stream == header.stream)?;
+
+        if stream.sid != header.sid {
+            // same stream from a different source, a standby is taking
+            // over. wait until the current source has actually gone quiet,
+            // so stray packets from one or the other don't flip us back
+            // and forth
+            let packet_duration = stream.format.packet_duration().to_std_duration_lossy();
+
+            if stream.last_audio.elapsed() < packet_duration {
+                return None;
+            }
+
+            if header.format == stream.header.format && header.audio_format() == stream.format {
+                log::info!("stream sid={} failed over to source sid={}",
+                    header.stream.get(), header.sid.get());
+                stream.failover(header);
+            } else {
+                log::info!("stream sid={} failed over to source sid={} with a different format, restarting",
+                    header.stream.get(), header.sid.get());
+                *stream = Stream::new(header, self.output, self.rate);
+                self.stats.clear();
+                self.clock_stats.clear();
+            }
+        }
+
+        Some(stream)
     }
 
-    pub fn receive_audio(&mut self, packet: Audio) {
+    pub fn receive_audio(&mut self, packet: Audio, peer: PeerId) {
         let now = time::now();
 
         let packet_dts = packet.header().dts;
+        let output = self.output;
+
+        let Some(stream) = self.prepare_stream(packet.header()) else {
+            // from a stream which has not taken over from ours, ignore
+            return;
+        };
 
-        let stream = self.prepare_stream(packet.header());
+        // audio past the pause point means the source has resumed, even if
+        // we never heard it say so
+        if let Some(Pause::Pausing { seq }) = stream.pause {
+            if packet.header().seq.get() >= seq {
+                stream.resume(output);
+            }
+        }
+
+        stream.last_audio = Instant::now();
+        stream.source = Some(peer);
         stream.queue.insert_packet(packet);
 
         if let Some(latency) = stream.network_latency() {
-            if let Some(clock_delta) = stream.clock_delta.median() {
+            if let Some(clock_delta) = stream.clock.predict(packet_dts.get()) {
                 let latency_usec = u64::try_from(latency.as_micros()).unwrap();
                 let delta_usec = clock_delta.as_micros();
-                let predict_dts = (now.0 - latency_usec).checked_add_signed(-delta_usec).unwrap();
-                let predict_diff = predict_dts as i64 - packet_dts.0 as i64;
+                let predict_dts = (now.get() - latency_usec).checked_add_signed(-delta_usec).unwrap();
+                let predict_diff = predict_dts as i64 - packet_dts.get() as i64;
                 self.stats.set_predict_offset(predict_diff)
             }
         }
     }
 
-    pub fn write_audio(&mut self, buffer: &mut [Frame], pts: Timestamp) -> usize {
+    pub fn receive_control(&mut self, packet: Control) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+
+        let data = packet.data();
+
+        if stream.sid != data.sid {
+            return;
+        }
+
+        // sources keep sending control packets while paused, which stops
+        // a paused stream from being mistaken for one that has ended
+        stream.last_audio = Instant::now();
+
+        match data.action {
+            ControlAction::PAUSE => {
+                if stream.pause.is_none() {
+                    log::info!("stream paused at seq={}", data.seq.get());
+                    stream.pause = Some(Pause::Pausing { seq: data.seq.get() });
+                }
+            }
+            ControlAction::RESUME => {
+                stream.resume(self.output);
+            }
+            ControlAction::END => {
+                if !stream.ending {
+                    log::info!("stream sid={} ending at seq={}", data.sid.get(), data.seq.get());
+                    stream.pause = Some(Pause::Pausing { seq: data.seq.get() });
+                    stream.ending = true;
+                }
+            }
+            action => {
+                log::debug!("unknown control action: {action:?}");
+            }
+        }
+    }
+
+    pub fn receive_parity(&mut self, packet: Parity) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+
+        if stream.sid != packet.header().sid {
+            return;
+        }
+
+        stream.queue.insert_parity(packet);
+    }
+
+    /// Builds a request for the stream source to retransmit packets missing
+    /// from the queue, if any of them could still arrive in time to play
+    pub fn retransmit_request(&mut self) -> Option<Retransmit> {
+        let stream = self.stream.as_mut()?;
+
+        // a retransmitted packet takes a full round trip to arrive
+        let round_trip = stream.network_latency()
+            .map(|latency| latency * 2)
+            .unwrap_or_default()
+            .max(MIN_RETRANSMIT_INTERVAL);
+
+        let packet_duration = stream.format.packet_duration().to_std_duration_lossy();
+
+        stream.retransmit.request(&stream.queue, stream.sid, packet_duration, round_trip, time::now())
+    }
+
+    /// Drops the current stream, going back to idle until the next one
+    fn end_stream(&mut self) {
+        self.stream = None;
+        self.stats.clear();
+        self.clock_stats.clear();
+        self.stats.set_stream(StreamStatus::Idle);
+    }
+
+    /// Writes interleaved audio in the receiver's output format to `buffer`,
+    /// returning the number of frames written
+    pub fn write_audio(&mut self, buffer: &mut [Sample], pts: Timestamp) -> usize {
         // get stream start timing information:
         let Some(stream) = self.stream.as_mut() else {
             // stream hasn't started, just fill buffer with silence and return
-            buffer[0..FRAMES_PER_PACKET].fill(Frame::zeroed());
+            buffer[0..self.output.samples_per_packet()].fill(0.0);
             return FRAMES_PER_PACKET;
         };
 
+        if stream.last_audio.elapsed() >= self.stream_timeout {
+            log::info!("no audio from stream sid={} for {}s, stream has gone away",
+                stream.sid.get(), self.stream_timeout.as_secs());
+            self.end_stream();
+            buffer[0..self.output.samples_per_packet()].fill(0.0);
+            return FRAMES_PER_PACKET;
+        }
+
+        if stream.hold(pts, self.output) {
+            if stream.ending {
+                log::info!("stream sid={} has ended", stream.sid.get());
+                self.end_stream();
+            } else {
+                self.stats.set_stream(StreamStatus::Paused);
+            }
+
+            buffer[0..self.output.samples_per_packet()].fill(0.0);
+            return FRAMES_PER_PACKET;
+        }
+
+        // jumping ahead of the stream, leave the queue be until it has
+        // caught up with us
+        if let Some(frames) = stream.pipeline.insert_silence(buffer) {
+            self.stats.set_stream(StreamStatus::Slew);
+            return frames;
+        }
+
         // get next packet from queue, or None if missing (packet loss)
         let packet = stream.queue.pop_front();
 
@@ -167,7 +589,11 @@ impl Receiver {
 
         // adjust resampler rate based on stream timing info
         if let Some(timing) = timing {
-            stream.pipeline.set_timing(timing);
+            if stream.pipeline.set_timing(timing) {
+                let offset = timing.real.delta(timing.play).abs().to_std_duration_lossy();
+                log::warn!("{}ms off stream, resyncing", offset.as_millis());
+                self.stats.add_resync();
+            }
 
             if stream.pipeline.slew() {
                 self.stats.set_stream(StreamStatus::Slew);
@@ -181,15 +607,28 @@ impl Receiver {
         // pass packet through decode pipeline
         let frames = stream.pipeline.process(packet.as_ref(), buffer);
 
+        // apply fades and volume
+        let samples = frames * usize::from(self.output.channels);
+        stream.gain.process(&mut buffer[0..samples], self.output.channels);
+        self.volume_gain.process(&mut buffer[0..samples], self.output.channels);
+
         // report stats and return
         self.stats.set_buffer_length(
-            SampleDuration::from_frame_count(
-                (FRAMES_PER_PACKET * stream.queue.len()).try_into().unwrap()));
+            SampleDuration::from_frames_at(
+                (FRAMES_PER_PACKET * stream.queue.len()).try_into().unwrap(),
+                stream.format.sample_rate));
 
         frames
     }
 }
 
+fn frames_in(duration: Duration, format: AudioFormat) -> usize {
+    SampleDuration::from_std_duration_lossy(duration)
+        .to_frames_at(format.sample_rate)
+        .try_into()
+        .unwrap()
+}
+
 struct Aggregate<T> {
     samples: [T; 64],
     count: usize,
@@ -237,12 +676,93 @@ pub struct ReceiveOpt {
     /// Size of decoded audio buffer in frames
     #[structopt(long, env = "BARK_RECEIVE_OUTPUT_BUFFER")]
     pub output_buffer: Option<u64>,
+
+    /// Sample rate to open the output device at in Hz [default: 48000]
+    #[structopt(long, env = "BARK_RECEIVE_OUTPUT_SAMPLE_RATE")]
+    pub output_sample_rate: Option<u32>,
+
+    /// Number of channels to open the output device with [default: 2]
+    #[structopt(long, env = "BARK_RECEIVE_OUTPUT_CHANNELS")]
+    pub output_channels: Option<u16>,
+
+    /// How the play time of audio is found: "userspace" estimates it from
+    /// the device delay when we write, otherwise ALSA timestamps the device
+    /// on the clock given, "monotonic-raw", "monotonic" or "realtime"
+    #[structopt(long, env = "BARK_RECEIVE_OUTPUT_TIMESTAMP", default_value = "monotonic-raw")]
+    pub output_timestamp: config::AudioTimestamp,
+
+    /// Zone to follow, only streams in this zone will be played
+    #[structopt(long, env = "BARK_ZONE", default_value = "")]
+    pub zone: String,
+
+    /// Which stream to play when a stream starts while another is already
+    /// playing: newest, priority, or first. Should match the sources
+    #[structopt(long, env = "BARK_TAKEOVER", default_value = "newest")]
+    pub takeover: Takeover,
+
+    /// Seconds without audio from a stream after which it is considered
+    /// gone, and the receiver goes idle
+    #[structopt(long, env = "BARK_RECEIVE_STREAM_TIMEOUT", default_value = "5")]
+    pub stream_timeout: u64,
+
+    /// Proportional gain of the playback rate controller: the fractional
+    /// rate adjustment made per second of offset from the stream
+    /// [default: 2.0]
+    #[structopt(long, env = "BARK_RECEIVE_RATE_KP")]
+    pub rate_kp: Option<f64>,
+
+    /// Integral gain of the playback rate controller, which learns the
+    /// steady drift between the output and stream clocks. Lower values
+    /// settle more slowly but are steadier on jittery networks [default: 0.1]
+    #[structopt(long, env = "BARK_RECEIVE_RATE_KI")]
+    pub rate_ki: Option<f64>,
+
+    /// Largest adjustment made to the playback rate, in percent [default: 1]
+    #[structopt(long, env = "BARK_RECEIVE_MAX_RATE_ADJUST")]
+    pub max_rate_adjust: Option<f64>,
+
+    /// Offset from the stream in milliseconds beyond which the receiver
+    /// reports it is slewing to catch up [default: 2]
+    #[structopt(long, env = "BARK_RECEIVE_SLEW_START_MS")]
+    pub slew_start_ms: Option<f64>,
+
+    /// Offset from the stream in milliseconds within which the receiver
+    /// reports it is back in sync [default: 1]
+    #[structopt(long, env = "BARK_RECEIVE_SLEW_STOP_MS")]
+    pub slew_stop_ms: Option<f64>,
+
+    /// Offset from the stream in milliseconds beyond which the receiver
+    /// jumps straight to the stream, skipping audio or inserting silence,
+    /// rather than slewing to it [default: 100]
+    #[structopt(long, env = "BARK_RECEIVE_RESYNC_THRESHOLD_MS")]
+    pub resync_threshold_ms: Option<f64>,
+}
+
+impl ReceiveOpt {
+    fn rate_params(&self) -> RateParams {
+        let default = RateParams::default();
+
+        let millis = |ms: f64| Duration::from_secs_f64(ms / 1000.0);
+
+        RateParams {
+            kp: self.rate_kp.unwrap_or(default.kp),
+            ki: self.rate_ki.unwrap_or(default.ki),
+            slew_start: self.slew_start_ms.map(millis).unwrap_or(default.slew_start),
+            slew_stop: self.slew_stop_ms.map(millis).unwrap_or(default.slew_stop),
+            max_adjust: self.max_rate_adjust.map(|percent| percent / 100.0).unwrap_or(default.max_adjust),
+            resync_threshold: self.resync_threshold_ms.map(millis).unwrap_or(default.resync_threshold),
+        }
+    }
 }
 
 pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
     let receiver_id = generate_receiver_id();
     let node = stats::node::get();
 
+    log::info!("receiver id: {}", receiver_id.get());
+    let zone = ZoneId::from_name(&opt.zone);
+    let rate = opt.rate_params();
+
     struct SharedState {
         pub recv: Receiver,
     }
@@ -255,10 +775,19 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
         buffer: opt.output_buffer
             .map(SampleDuration::from_frame_count)
             .unwrap_or(DEFAULT_BUFFER),
+        format: AudioFormat {
+            sample_rate: opt.output_sample_rate
+                .map(SampleRate)
+                .unwrap_or(bark_protocol::SAMPLE_RATE),
+            channels: opt.output_channels
+                .map(ChannelCount)
+                .unwrap_or(bark_protocol::CHANNELS),
+        },
+        timestamp: opt.output_timestamp,
     }).map_err(RunError::OpenAudioDevice)?;
 
     let state = Arc::new(Mutex::new(SharedState {
-        recv: Receiver::new(),
+        recv: Receiver::new(output.format(), opt.takeover, Duration::from_secs(opt.stream_timeout), rate),
     }));
 
     std::thread::spawn({
@@ -266,26 +795,26 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
         move || {
             thread::set_name("bark/audio");
 
+            // this should be large enough for `write_audio` to process an
+            // entire packet with:
+            let channels = output.format().channels;
+            let mut buffer = vec![0.0; MAX_OUTPUT_FRAMES_PER_PACKET * usize::from(channels)];
+
             loop {
                 let mut state = state.lock().unwrap();
 
-                let delay = output.delay().unwrap();
-                state.recv.stats.set_output_latency(delay);
+                let position = output.position().unwrap();
+                state.recv.stats.set_output_latency(position.delay);
 
-                let pts = time::now();
-                let pts = Timestamp::from_micros_lossy(pts);
-                let pts = pts.add(delay);
+                let pts = position.timestamp.add(position.delay);
 
-                // this should be large enough for `write_audio` to process an
-                // entire packet with:
-                let mut buffer = [Frame::zeroed(); FRAMES_PER_PACKET * 2];
                 let count = state.recv.write_audio(&mut buffer, pts);
 
                 // drop lock before calling `Output::write` (blocking!)
                 drop(state);
 
                 // send audio to ALSA
-                match output.write(&buffer[0..count]) {
+                match output.write(&buffer[0..count * usize::from(channels)]) {
                     Ok(()) => {}
                     Err(e) => {
                         log::error!("error playing audio: {e}");
@@ -296,16 +825,34 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
         }
     });
 
-    let socket = Socket::open(opt.socket)
+    let protocol = ProtocolSocket::open(opt.socket)
         .map_err(RunError::Listen)?;
 
-    let protocol = ProtocolSocket::new(socket);
+    let protocol = Arc::new(protocol);
+
+    std::thread::spawn({
+        let state = state.clone();
+        let protocol = Arc::clone(&protocol);
+        move || {
+            thread::set_name("bark/timesync");
+
+            loop {
+                std::thread::sleep(TIME_REQUEST_INTERVAL);
+
+                let request = state.lock().unwrap().recv.time_request(receiver_id);
+
+                if let Some((time, peer)) = request {
+                    let _ = protocol.send_to(time.as_packet(), peer);
+                }
+            }
+        }
+    });
 
     thread::set_name("bark/network");
     thread::set_realtime_priority();
 
     loop {
-        let (packet, peer) = protocol.recv_from().map_err(RunError::Receive)?;
+        let (packet, peer, received) = protocol.recv_timestamped().map_err(RunError::Receive)?;
 
         match packet.parse() {
             Some(PacketKind::Time(mut time)) => {
@@ -315,10 +862,15 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
                     continue;
                 }
 
+                if time.data().zone != zone {
+                    // from a stream in another zone
+                    continue;
+                }
+
                 match time.data().phase() {
                     Some(TimePhase::Broadcast) => {
                         let data = time.data_mut();
-                        data.receive_2 = time::now();
+                        data.receive_2 = received;
                         data.rid = receiver_id;
 
                         protocol.send_to(time.as_packet(), peer)
@@ -335,23 +887,67 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
                 }
             }
             Some(PacketKind::Audio(packet)) => {
+                if packet.header().zone != zone {
+                    continue;
+                }
+
                 let mut state = state.lock().unwrap();
-                state.recv.receive_audio(packet);
+                state.recv.receive_audio(packet, peer);
+                let retransmit = state.recv.retransmit_request();
+                drop(state);
+
+                if let Some(retransmit) = retransmit {
+                    log::debug!("requesting retransmit from {peer}: base_seq={}, missing={:#x}",
+                        retransmit.data().base_seq.get(),
+                        retransmit.data().missing.get());
+
+                    let _ = protocol.send_to(retransmit.as_packet(), peer);
+                }
             }
             Some(PacketKind::StatsRequest(_)) => {
                 let state = state.lock().unwrap();
                 let sid = state.recv.current_session().unwrap_or(SessionId::zeroed());
                 let receiver = *state.recv.stats();
+                let clock = *state.recv.clock_stats();
                 drop(state);
 
-                let reply = StatsReply::receiver(sid, receiver, node)
+                let reply = StatsReply::receiver(sid, &opt.zone, receiver, clock, node)
                     .expect("allocate StatsReply packet");
 
                 let _ = protocol.send_to(reply.as_packet(), peer);
             }
+            Some(PacketKind::Metadata(packet)) => {
+                let mut state = state.lock().unwrap();
+                state.recv.receive_metadata(packet);
+            }
+            Some(PacketKind::Parity(packet)) => {
+                let mut state = state.lock().unwrap();
+                state.recv.receive_parity(packet);
+            }
+            Some(PacketKind::Control(packet)) => {
+                let mut state = state.lock().unwrap();
+                state.recv.receive_control(packet);
+            }
+            Some(PacketKind::Volume(packet)) => {
+                let data = packet.data();
+
+                if !data.rid.matches(&receiver_id) {
+                    continue;
+                }
+
+                if !data.hostname().is_empty() && data.hostname() != stats::node::hostname(&node) {
+                    continue;
+                }
+
+                let mut state = state.lock().unwrap();
+                state.recv.set_volume(data.volume(), data.mute());
+            }
             Some(PacketKind::StatsReply(_)) => {
                 // ignore
             }
+            Some(PacketKind::Retransmit(_)) => {
+                // ignore
+            }
             None => {
                 // unknown packet type, ignore
             }
@@ -360,5 +956,5 @@ pub fn run(opt: ReceiveOpt) -> Result<(), RunError> {
 }
 
 pub fn generate_receiver_id() -> ReceiverId {
-    ReceiverId(rand::random())
+    ReceiverId::new(rand::random())
 }
@@ -1,20 +1,58 @@
-use std::io;
-use std::net::{Ipv4Addr, UdpSocket, SocketAddr, SocketAddrV4};
+use std::collections::{HashSet, VecDeque};
+use std::convert::Infallible;
+use std::io::{self, IoSliceMut};
+use std::mem::{self, size_of};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::os::fd::AsRawFd;
+use std::ptr;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 use derive_more::Display;
 use nix::poll::{PollFd, PollFlags};
-use socket2::{Domain, Type};
+use nix::sys::socket::{self as nix_socket, ControlMessageOwned, MsgFlags, SockaddrStorage, Timestamps};
+use nix::sys::time::TimeSpec;
+use socket2::{Domain, InterfaceIndexOrAddress, Type};
 use structopt::StructOpt;
 
 use bark_protocol::buffer::PacketBuffer;
-use bark_protocol::packet::Packet;
+use bark_protocol::packet::{Packet, MAX_PACKET_SIZE};
+use bark_protocol::types::{Compatibility, Magic, PacketHeader, TimestampMicros};
+
+use crate::auth::Authenticator;
+use crate::config;
+use crate::time;
 use thiserror::Error;
 
 // expedited forwarding - IP header field indicating that switches should
 // prioritise our packets for minimal delay
 const IPTOS_DSCP_EF: u32 = 0xb8;
 
+// error queue constants from linux/errqueue.h, which libc doesn't have
+const SO_EE_ORIGIN_TIMESTAMPING: u8 = 4;
+const SCM_TSTAMP_SND: u32 = 0;
+
+// set on every socket. SOF_TIMESTAMPING_SOFTWARE also has the kernel report
+// send timestamps, but only for packets sent with TX_TIMESTAMPING
+const RX_TIMESTAMPING: libc::c_uint = libc::SOF_TIMESTAMPING_SOFTWARE
+    | libc::SOF_TIMESTAMPING_RX_SOFTWARE;
+
+// requested per packet in a control message, so that the kernel only
+// timestamps the packets we want timestamps of
+const TX_TIMESTAMPING: u32 = libc::SOF_TIMESTAMPING_TX_SOFTWARE;
+
+// how many sent packets we keep kernel timestamps for at once
+const MAX_TX_TIMESTAMPS: usize = 16;
+
+// how many peers we remember having warned about. source addresses of
+// packets we warn about aren't authenticated and can be spoofed, so this is
+// bounded, and once full we forget them all and may warn again
+const MAX_WARNED_PEERS: usize = 256;
+
+// the kernel loops each timestamped packet back to us with its headers down
+// to the link layer in front
+const TX_LOOPBACK_HEADROOM: usize = 128;
+
 #[derive(Debug, Error)]
 pub enum ListenError {
     #[error("creating socket: {0}")]
@@ -24,27 +62,126 @@ pub enum ListenError {
     #[error("setting SO_BROADCAST: {0}")]
     SetBroadcast(io::Error),
     #[error("binding {0}: {1}")]
-    Bind(SocketAddrV4, io::Error),
+    Bind(SocketAddr, io::Error),
     #[error("joining multicast group {0}: {1}")]
-    JoinMulticastGroup(Ipv4Addr, io::Error),
+    JoinMulticastGroup(IpAddr, io::Error),
+    #[error("setting multicast interface: {0}")]
+    SetMulticastInterface(io::Error),
+    #[error("unknown network interface {0}: {1}")]
+    UnknownInterface(String, nix::Error),
+    #[error("no multicast group, unicast destination, or listen address given")]
+    NoAddress,
+    #[error("encryption requires a pre-shared key to be set")]
+    EncryptWithoutKey,
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct SocketOpt {
     #[structopt(long, name="addr", env = "BARK_MULTICAST")]
-    /// Multicast group address including port, eg. 224.100.100.100:1530
-    pub multicast: SocketAddrV4,
+    /// Multicast group address including port, eg. 224.100.100.100:1530 or
+    /// [ff15::b4:1530]:1530
+    pub multicast: Option<SocketAddr>,
+
+    #[structopt(long, env = "BARK_UNICAST", use_delimiter = true)]
+    /// Addresses of receivers to send packets to directly by unicast,
+    /// separated by commas. Can be used with or instead of multicast
+    pub unicast: Vec<SocketAddr>,
+
+    #[structopt(long, env = "BARK_LISTEN")]
+    /// Address to listen on for packets sent by unicast, eg. 0.0.0.0:1530
+    pub listen: Option<SocketAddr>,
+
+    #[structopt(long, env = "BARK_INTERFACE")]
+    /// Network interface to join the multicast group on, by name or index.
+    /// Required for link-local IPv6 groups, unless the address carries a
+    /// scope id, eg. [ff12::b4:1530%2]:1530
+    pub interface: Option<Interface>,
+
+    #[structopt(long, env = "BARK_KEY", hide_env_values = true)]
+    /// Pre-shared key used to authenticate all packets. When set, packets
+    /// without a valid MAC are dropped. All nodes must use the same key
+    pub key: Option<String>,
+
+    #[structopt(long, env = "BARK_ENCRYPT")]
+    /// Encrypt packets with the pre-shared key: "audio" encrypts audio
+    /// packets only, "all" also encrypts time, stats, and metadata packets.
+    /// All nodes must use the same setting
+    pub encrypt: Option<config::Encrypt>,
+
+    #[structopt(long, env = "BARK_TIMESTAMPS", default_value = "userspace")]
+    /// Where the packet timestamps used to sync clocks come from:
+    /// "userspace" reads the clock once a packet has been received, "kernel"
+    /// uses the kernel's record of when packets arrived and were sent, which
+    /// isn't thrown off by how long it takes bark to be scheduled
+    pub timestamps: config::Timestamps,
+}
+
+/// Network interface given by name or by index
+#[derive(Debug, Clone)]
+pub enum Interface {
+    Index(u32),
+    Name(String),
+}
+
+impl FromStr for Interface {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(index) => Ok(Interface::Index(index)),
+            Err(_) => Ok(Interface::Name(s.to_owned())),
+        }
+    }
+}
+
+impl Interface {
+    pub fn index(&self) -> Result<u32, ListenError> {
+        match self {
+            Interface::Index(index) => Ok(*index),
+            Interface::Name(name) => nix::net::if_::if_nametoindex(name.as_str())
+                .map_err(|e| ListenError::UnknownInterface(name.clone(), e)),
+        }
+    }
 }
 
 pub struct Socket {
-    multicast: SocketAddrV4,
+    // where broadcast packets are sent, the multicast group if there is one
+    // plus any unicast destinations
+    destinations: Vec<SocketAddr>,
 
     // used to send unicast + multicast packets, as well as receive unicast replies
-    // bound to 0.0.0.0:0, aka. OS picks a port
+    // bound to 0.0.0.0:0 (or [::]:0), aka. OS picks a port
     tx: UdpSocket,
 
-    // uses to receive multicast packets
-    rx: UdpSocket,
+    // used to receive multicast packets, and unicast packets sent to our
+    // listen address
+    rx: Vec<UdpSocket>,
+
+    // whether to ask the kernel for its receive timestamp of each packet
+    kernel_timestamps: bool,
+
+    // kernel timestamps of packets sent from tx, if the kernel supports them
+    tx_timestamps: Option<TxTimestamps>,
+}
+
+/// Kernel timestamps of packets sent from the tx socket. The kernel hands
+/// each timestamp back along with the packet it belongs to, which is how we
+/// tell them apart
+struct TxTimestamps {
+    // sent packets we want the timestamps of, oldest first
+    wanted: Mutex<VecDeque<TxTimestamp>>,
+}
+
+struct TxTimestamp {
+    packet: Vec<u8>,
+    // time the packet was sent according to our own clock, which is how
+    // callers identify it
+    sent: TimestampMicros,
+    // realtime clock offset as of sending. the kernel timestamps on the
+    // realtime clock, and we only get to convert its timestamp once it
+    // reaches us, by when the realtime clock may have been stepped
+    realtime_offset: i64,
+    kernel: Option<TimestampMicros>,
 }
 
 #[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -52,74 +189,461 @@ pub struct PeerId(SocketAddr);
 
 impl Socket {
     pub fn open(opt: SocketOpt) -> Result<Socket, ListenError> {
-        let group = *opt.multicast.ip();
-        let port = opt.multicast.port();
+        let interface = opt.interface
+            .map(|interface| interface.index())
+            .transpose()?;
+
+        let (tx, mut rx, mut destinations) = match opt.multicast {
+            Some(SocketAddr::V4(addr)) => {
+                let group = *addr.ip();
+                let port = addr.port();
+                let interface = interface.unwrap_or(0);
+
+                let tx = open_multicast_v4(group, interface, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+                let rx = open_multicast_v4(group, interface, SocketAddrV4::new(group, port))?;
+
+                (tx, vec![rx], vec![SocketAddr::V4(addr)])
+            }
+            Some(SocketAddr::V6(addr)) => {
+                let group = *addr.ip();
+                let port = addr.port();
+
+                // interface given explicitly takes precedence over any
+                // scope id in the group address
+                let interface = interface.unwrap_or(addr.scope_id());
+
+                let tx = open_multicast_v6(group, interface, SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0))?;
+                let rx = open_multicast_v6(group, interface, SocketAddrV6::new(group, port, 0, interface))?;
+
+                // link-local groups are only reachable when we send with
+                // the scope id of the interface set
+                let multicast = SocketAddrV6::new(group, port, 0, interface);
+
+                (tx, vec![rx], vec![SocketAddr::V6(multicast)])
+            }
+            None => {
+                // unicast only, pick address family from whichever address
+                // we were given
+                let bind = match opt.listen.or(opt.unicast.first().copied()) {
+                    Some(SocketAddr::V4(_)) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    Some(SocketAddr::V6(_)) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                    None => { return Err(ListenError::NoAddress); }
+                };
+
+                (bind_socket(bind)?, vec![], vec![])
+            }
+        };
 
-        let tx = open_multicast(group, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
-        let rx = open_multicast(group, SocketAddrV4::new(group, port))?;
+        if let Some(listen) = opt.listen {
+            rx.push(bind_socket(listen)?);
+        }
+
+        destinations.extend(opt.unicast);
+
+        let kernel_timestamps = opt.timestamps == config::Timestamps::Kernel;
+
+        let tx_timestamps = if kernel_timestamps {
+            for socket in &rx {
+                enable_rx_timestamps(socket);
+            }
+
+            match set_timestamping(&tx, RX_TIMESTAMPING) {
+                Ok(()) => Some(TxTimestamps::new()),
+                Err(e) => {
+                    log::warn!("kernel send timestamps unavailable, using userspace send times: {e}");
+                    enable_rx_timestamps(&tx);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
         Ok(Socket {
-            multicast: SocketAddrV4::new(group, port),
+            destinations,
             tx: tx.into(),
-            rx: rx.into(),
+            rx: rx.into_iter().map(UdpSocket::from).collect(),
+            kernel_timestamps,
+            tx_timestamps,
         })
     }
 
+    /// Sends to the multicast group and all unicast destinations. Succeeds
+    /// if the packet could be sent to at least one of them, an unreachable
+    /// receiver shouldn't stop everyone else from hearing the stream
     pub fn broadcast(&self, msg: &[u8]) -> Result<(), io::Error> {
-        self.tx.send_to(msg, self.multicast)?;
-        Ok(())
+        self.broadcast_at(msg, None)
+    }
+
+    /// As [`Socket::broadcast`], also keeping the kernel's timestamp of when
+    /// the packet was sent, if available, for [`Socket::tx_timestamp`] to
+    /// look up by `sent`, our own timestamp of the send
+    pub fn broadcast_timestamped(&self, msg: &[u8], sent: TimestampMicros) -> Result<(), io::Error> {
+        self.broadcast_at(msg, Some(sent))
+    }
+
+    fn broadcast_at(&self, msg: &[u8], mut timestamp: Option<TimestampMicros>) -> Result<(), io::Error> {
+        let mut sent = false;
+        let mut error = None;
+
+        for dest in &self.destinations {
+            // only the first send of a broadcast is timestamped, the rest
+            // follow it within microseconds
+            match self.send_tx(msg, dest, timestamp) {
+                Ok(()) => { sent = true; timestamp = None; }
+                Err(e) => { error = Some(e); }
+            }
+        }
+
+        match error {
+            Some(e) if !sent => Err(e),
+            _ => Ok(()),
+        }
     }
 
     pub fn send_to(&self, msg: &[u8], dest: PeerId) -> Result<(), io::Error> {
-        self.tx.send_to(msg, dest.0)?;
-        Ok(())
+        self.send_tx(msg, &dest.0, None)
+    }
+
+    /// As [`Socket::send_to`], keeping the kernel's timestamp of the send
+    /// as [`Socket::broadcast_timestamped`] does
+    pub fn send_to_timestamped(&self, msg: &[u8], dest: PeerId, sent: TimestampMicros) -> Result<(), io::Error> {
+        self.send_tx(msg, &dest.0, Some(sent))
+    }
+
+    fn send_tx(&self, msg: &[u8], dest: &SocketAddr, sent: Option<TimestampMicros>) -> Result<(), io::Error> {
+        let (Some(tx_timestamps), Some(sent)) = (&self.tx_timestamps, sent) else {
+            self.tx.send_to(msg, dest)?;
+            return Ok(());
+        };
+
+        // ask for the timestamp before sending, the kernel can queue it
+        // before sendmsg has even returned
+        tx_timestamps.want(msg, sent);
+
+        let result = send_timestamped(&self.tx, msg, dest);
+
+        if result.is_err() {
+            tx_timestamps.unwant(sent);
+        }
+
+        result
+    }
+
+    /// The kernel's timestamp of the packet sent at `sent` by
+    /// [`Socket::broadcast_timestamped`] or [`Socket::send_to_timestamped`],
+    /// once it is known
+    pub fn tx_timestamp(&self, sent: TimestampMicros) -> Option<TimestampMicros> {
+        self.tx_timestamps.as_ref()?.get(sent)
+    }
+
+    /// Receives a packet from any of our sockets, along with the time it
+    /// was received: the kernel's timestamp when using kernel timestamps
+    /// and one is available, otherwise the time we read it
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, PeerId, TimestampMicros), io::Error> {
+        let sockets = std::iter::once(&self.tx)
+            .chain(&self.rx)
+            .collect::<Vec<_>>();
+
+        loop {
+            let mut poll = sockets.iter()
+                .map(|socket| PollFd::new(socket.as_raw_fd(), PollFlags::POLLIN))
+                .collect::<Vec<_>>();
+
+            nix::poll::poll(&mut poll, -1)?;
+
+            let events = poll.iter()
+                .map(|fd| fd.revents().unwrap_or(PollFlags::empty()))
+                .collect::<Vec<_>>();
+
+            // timestamps of packets we've sent are delivered through the tx
+            // socket's error queue, which poll reports as an error
+            if let Some(tx_timestamps) = &self.tx_timestamps {
+                if events[0].contains(PollFlags::POLLERR) {
+                    tx_timestamps.drain(&self.tx);
+                }
+            }
+
+            let readable = events.iter().position(|events| {
+                if self.tx_timestamps.is_some() {
+                    events.contains(PollFlags::POLLIN)
+                } else {
+                    !events.is_empty()
+                }
+            });
+
+            if let Some(readable) = readable {
+                return self.recv_on(sockets[readable], buf);
+            }
+        }
+    }
+
+    fn recv_on(&self, socket: &UdpSocket, buf: &mut [u8]) -> Result<(usize, PeerId, TimestampMicros), io::Error> {
+        if !self.kernel_timestamps {
+            let (nbytes, addr) = socket.recv_from(buf)?;
+            return Ok((nbytes, PeerId(addr), time::now()));
+        }
+
+        let mut iov = [IoSliceMut::new(buf)];
+        let mut cmsg = nix::cmsg_space!(Timestamps);
+
+        let msg = nix_socket::recvmsg::<SockaddrStorage>(
+            socket.as_raw_fd(), &mut iov, Some(&mut cmsg), MsgFlags::empty())?;
+
+        let timestamp = msg.cmsgs()
+            .find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmTimestampsns(timestamps) => Some(timestamps.system),
+                ControlMessageOwned::ScmTimestampns(timestamp) => Some(timestamp),
+                _ => None,
+            })
+            .map(time::from_realtime)
+            // the kernel didn't timestamp this packet, do it ourselves
+            .unwrap_or_else(time::now);
+
+        let addr = msg.address.as_ref()
+            .and_then(socket_addr)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "received packet without source address"))?;
+
+        Ok((msg.bytes, PeerId(addr), timestamp))
+    }
+}
+
+impl TxTimestamps {
+    fn new() -> Self {
+        TxTimestamps {
+            wanted: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn want(&self, packet: &[u8], sent: TimestampMicros) {
+        let mut wanted = self.wanted.lock().unwrap();
+
+        if wanted.len() == MAX_TX_TIMESTAMPS {
+            wanted.pop_front();
+        }
+
+        wanted.push_back(TxTimestamp {
+            packet: packet.to_vec(),
+            sent,
+            realtime_offset: time::realtime_offset(),
+            kernel: None,
+        });
+    }
+
+    fn unwant(&self, sent: TimestampMicros) {
+        self.wanted.lock().unwrap().retain(|timestamp| timestamp.sent.get() != sent.get());
     }
 
-    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, PeerId), io::Error> {
-        let mut poll = [
-            PollFd::new(self.tx.as_raw_fd(), PollFlags::POLLIN),
-            PollFd::new(self.rx.as_raw_fd(), PollFlags::POLLIN),
-        ];
+    fn get(&self, sent: TimestampMicros) -> Option<TimestampMicros> {
+        self.wanted.lock().unwrap().iter()
+            .find(|timestamp| timestamp.sent.get() == sent.get())
+            .and_then(|timestamp| timestamp.kernel)
+    }
 
-        nix::poll::poll(&mut poll, -1)?;
+    /// Reads all timestamps queued on the socket's error queue, keeping the
+    /// ones we want
+    fn drain(&self, socket: &UdpSocket) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE + TX_LOOPBACK_HEADROOM];
 
-        let (nbytes, addr) =
-            if poll[0].any() == Some(true) {
-                self.tx.recv_from(buf)?
-            } else if poll[1].any() == Some(true) {
-                self.rx.recv_from(buf)?
-            } else {
-                unreachable!("poll returned with no readable sockets");
+        while let Some(sent) = recv_tx_timestamp(socket, &mut buf) {
+            let Some((len, timestamp)) = sent else {
+                continue;
             };
 
-        Ok((nbytes, PeerId(addr)))
+            // the looped back packet has the headers it was sent with in
+            // front, our payload is what comes after them
+            let looped = &buf[..len];
+
+            let mut wanted = self.wanted.lock().unwrap();
+
+            if let Some(wanted) = wanted.iter_mut().find(|wanted| looped.ends_with(&wanted.packet)) {
+                wanted.kernel = Some(time::from_realtime_offset(wanted.realtime_offset, timestamp));
+            }
+        }
+
+        // anything else that put the socket in an error state has been
+        // dealt with as far as we're concerned, clear it so that poll stops
+        // reporting it
+        let _ = socket.take_error();
     }
 }
 
-fn open_multicast(group: Ipv4Addr, bind: SocketAddrV4) -> Result<socket2::Socket, ListenError> {
-    let socket = bind_socket(bind)?;
+fn socket_addr(addr: &SockaddrStorage) -> Option<SocketAddr> {
+    if let Some(addr) = addr.as_sockaddr_in() {
+        return Some(SocketAddr::V4((*addr).into()));
+    }
+
+    addr.as_sockaddr_in6()
+        .map(|addr| SocketAddr::V6((*addr).into()))
+}
 
-    // join multicast group
-    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
-        .map_err(|e| ListenError::JoinMulticastGroup(group, e))?;
+/// Asks the kernel to timestamp packets as they arrive on the socket,
+/// preferring SO_TIMESTAMPING and falling back to SO_TIMESTAMPNS. Packets
+/// on sockets where neither is supported are timestamped by us instead
+fn enable_rx_timestamps(socket: &socket2::Socket) {
+    let Err(e) = set_timestamping(socket, RX_TIMESTAMPING) else {
+        return;
+    };
+
+    log::debug!("SO_TIMESTAMPING unavailable, trying SO_TIMESTAMPNS: {e}");
+
+    if let Err(e) = nix_socket::setsockopt(socket.as_raw_fd(), nix_socket::sockopt::ReceiveTimestampns, &true) {
+        log::warn!("kernel receive timestamps unavailable, using userspace receive times: {e}");
+    }
+}
+
+/// Reads one message from the socket's error queue into `buf`. Returns None
+/// once the queue is empty, otherwise the length of the packet looped back
+/// and its send timestamp on the realtime clock, if the message was one
+fn recv_tx_timestamp(socket: &UdpSocket, buf: &mut [u8]) -> Option<Option<(usize, TimeSpec)>> {
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut cmsg = nix::cmsg_space!(Timestamps, libc::sock_extended_err, libc::sockaddr_in6);
+
+    let msg = nix_socket::recvmsg::<()>(socket.as_raw_fd(), &mut iov, Some(&mut cmsg),
+        MsgFlags::MSG_ERRQUEUE | MsgFlags::MSG_DONTWAIT).ok()?;
+
+    let mut timestamp = None;
+    let mut is_send = false;
+
+    for cmsg in msg.cmsgs() {
+        match cmsg {
+            ControlMessageOwned::ScmTimestampsns(timestamps) => {
+                timestamp = Some(timestamps.system);
+            }
+            ControlMessageOwned::Ipv4RecvErr(err, _) |
+            ControlMessageOwned::Ipv6RecvErr(err, _)
+                if err.ee_origin == SO_EE_ORIGIN_TIMESTAMPING && err.ee_info == SCM_TSTAMP_SND =>
+            {
+                is_send = true;
+            }
+            _ => {}
+        }
+    }
+
+    // a truncated packet is cut short at the end, where our payload is, so
+    // it can't be matched to anything
+    if !is_send || msg.flags.contains(MsgFlags::MSG_TRUNC) {
+        return Some(None);
+    }
+
+    Some(timestamp.map(|timestamp| (msg.bytes, timestamp)))
+}
+
+/// Sends a packet asking the kernel to timestamp it as it goes out. The
+/// timestamp arrives later on the socket's error queue
+fn send_timestamped(socket: &UdpSocket, msg: &[u8], dest: &SocketAddr) -> Result<(), io::Error> {
+    let dest = socket2::SockAddr::from(*dest);
+
+    let mut iov = libc::iovec {
+        iov_base: msg.as_ptr().cast_mut().cast(),
+        iov_len: msg.len(),
+    };
+
+    // u64s so that the control message header is suitably aligned
+    let mut control = [0u64; 4];
+
+    let control_len = unsafe { libc::CMSG_SPACE(size_of::<u32>() as u32) } as usize;
+    assert!(control_len <= mem::size_of_val(&control));
+
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_name = dest.as_ptr().cast_mut().cast();
+    hdr.msg_namelen = dest.len();
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr().cast();
+    hdr.msg_controllen = control_len as _;
+
+    let rc = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SO_TIMESTAMPING;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u32>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<u32>(), TX_TIMESTAMPING);
+
+        libc::sendmsg(socket.as_raw_fd(), &hdr, 0)
+    };
+
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn set_timestamping(socket: &socket2::Socket, flags: libc::c_uint) -> Result<(), io::Error> {
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            (&flags as *const libc::c_uint).cast(),
+            size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn open_multicast_v4(group: Ipv4Addr, interface: u32, bind: SocketAddrV4) -> Result<socket2::Socket, ListenError> {
+    let socket = bind_socket(SocketAddr::V4(bind))?;
+
+    // join multicast group, on any interface unless one was given
+    let join = if interface == 0 {
+        socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+    } else {
+        socket.join_multicast_v4_n(&group, &InterfaceIndexOrAddress::Index(interface))
+    };
+
+    join.map_err(|e| ListenError::JoinMulticastGroup(IpAddr::V4(group), e))?;
 
     // set opts
     socket.set_broadcast(true).map_err(ListenError::SetBroadcast)?;
     let _ = socket.set_multicast_loop_v4(true);
 
-    Ok(socket.into())
+    Ok(socket)
+}
+
+fn open_multicast_v6(group: Ipv6Addr, interface: u32, bind: SocketAddrV6) -> Result<socket2::Socket, ListenError> {
+    let socket = bind_socket(SocketAddr::V6(bind))?;
+
+    // join multicast group, interface 0 lets the kernel choose
+    socket.join_multicast_v6(&group, interface)
+        .map_err(|e| ListenError::JoinMulticastGroup(IpAddr::V6(group), e))?;
+
+    if interface != 0 {
+        socket.set_multicast_if_v6(interface)
+            .map_err(ListenError::SetMulticastInterface)?;
+    }
+
+    let _ = socket.set_multicast_loop_v6(true);
+
+    Ok(socket)
 }
 
-fn bind_socket(bind: SocketAddrV4) -> Result<socket2::Socket, ListenError> {
-    let socket = socket2::Socket::new(Domain::IPV4, Type::DGRAM, None)
+fn bind_socket(bind: SocketAddr) -> Result<socket2::Socket, ListenError> {
+    let socket = socket2::Socket::new(Domain::for_address(bind), Type::DGRAM, None)
         .map_err(ListenError::Socket)?;
 
     socket.set_reuse_address(true).map_err(ListenError::SetReuseAddr)?;
 
-    if let Err(e) = socket.set_tos(IPTOS_DSCP_EF) {
+    let tos = match bind {
+        SocketAddr::V4(_) => socket.set_tos(IPTOS_DSCP_EF),
+        SocketAddr::V6(_) => socket.set_tclass_v6(IPTOS_DSCP_EF),
+    };
+
+    if let Err(e) = tos {
         log::warn!("failed to set IPTOS_DSCP_EF: {e:?}");
     }
 
+    if bind.is_ipv6() {
+        // don't also receive ipv4 traffic on ipv6 sockets
+        let _ = socket.set_only_v6(true);
+    }
+
     socket.bind(&bind.into()).map_err(|e| ListenError::Bind(bind, e))?;
 
     Ok(socket)
@@ -127,42 +651,183 @@ fn bind_socket(bind: SocketAddrV4) -> Result<socket2::Socket, ListenError> {
 
 pub struct ProtocolSocket {
     socket: Socket,
+    auth: Option<Authenticator>,
+    encrypt: Option<config::Encrypt>,
+    // peers we've already warned about, so that we only log once per peer
+    // for each kind of problem, up to MAX_WARNED_PEERS
+    warned_peers: Mutex<HashSet<(PeerId, Warning)>>,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Warning {
+    IncompatibleVersion,
+    Unauthenticated,
 }
 
 impl ProtocolSocket {
-    pub fn new(socket: Socket) -> Self {
-        ProtocolSocket { socket }
+    pub fn open(opt: SocketOpt) -> Result<Self, ListenError> {
+        let encrypt = opt.encrypt;
+
+        let auth = match (opt.key.as_deref(), encrypt) {
+            (Some(key), None) => Some(Authenticator::new(key)),
+            (Some(key), Some(_)) => Some(Authenticator::new(key).with_encryption()),
+            (None, None) => None,
+            (None, Some(_)) => { return Err(ListenError::EncryptWithoutKey); }
+        };
+
+        let socket = Socket::open(opt)?;
+
+        Ok(ProtocolSocket {
+            socket,
+            auth,
+            encrypt,
+            warned_peers: Mutex::new(HashSet::new()),
+        })
     }
 
     pub fn broadcast(&self, packet: &Packet) -> Result<(), io::Error> {
-        self.socket.broadcast(packet.as_buffer().as_bytes())
+        match &self.auth {
+            Some(auth) => self.socket.broadcast(&self.sign(auth, packet)),
+            None => self.socket.broadcast(packet.as_buffer().as_bytes()),
+        }
+    }
+
+    /// Broadcasts `packet`, keeping the kernel's timestamp of when it was
+    /// sent for [`ProtocolSocket::tx_timestamp`], if kernel timestamps are
+    /// in use. `sent` is our own timestamp of the send
+    pub fn broadcast_timestamped(&self, packet: &Packet, sent: TimestampMicros) -> Result<(), io::Error> {
+        match &self.auth {
+            Some(auth) => self.socket.broadcast_timestamped(&self.sign(auth, packet), sent),
+            None => self.socket.broadcast_timestamped(packet.as_buffer().as_bytes(), sent),
+        }
+    }
+
+    /// The kernel's timestamp of the packet sent at `sent` by
+    /// [`ProtocolSocket::broadcast_timestamped`] or
+    /// [`ProtocolSocket::send_to_timestamped`], once it is known
+    pub fn tx_timestamp(&self, sent: TimestampMicros) -> Option<TimestampMicros> {
+        self.socket.tx_timestamp(sent)
     }
 
     pub fn send_to(&self, packet: &Packet, peer: PeerId) -> Result<(), io::Error> {
-        self.socket.send_to(packet.as_buffer().as_bytes(), peer)
+        match &self.auth {
+            Some(auth) => self.socket.send_to(&self.sign(auth, packet), peer),
+            None => self.socket.send_to(packet.as_buffer().as_bytes(), peer),
+        }
+    }
+
+    /// Sends `packet` to `peer`, keeping the kernel's timestamp of when it
+    /// was sent as [`ProtocolSocket::broadcast_timestamped`] does
+    pub fn send_to_timestamped(&self, packet: &Packet, peer: PeerId, sent: TimestampMicros) -> Result<(), io::Error> {
+        match &self.auth {
+            Some(auth) => self.socket.send_to_timestamped(&self.sign(auth, packet), peer, sent),
+            None => self.socket.send_to_timestamped(packet.as_buffer().as_bytes(), peer, sent),
+        }
     }
 
-    fn recv_buffer_from(&self) -> Result<(PacketBuffer, PeerId), io::Error> {
+    fn sign(&self, auth: &Authenticator, packet: &Packet) -> Vec<u8> {
+        let seal = self.is_sealed(packet.header().magic);
+        auth.sign(packet.as_buffer().as_bytes(), seal)
+    }
+
+    /// Whether packets with this magic have their body encrypted
+    fn is_sealed(&self, magic: Magic) -> bool {
+        match self.encrypt {
+            None => false,
+            // parity packets are derived from audio, so must be sealed
+            // whenever audio is
+            Some(config::Encrypt::Audio) => matches!(magic,
+                Magic::AUDIO | Magic::PARITY),
+            Some(config::Encrypt::All) => matches!(magic,
+                Magic::AUDIO | Magic::PARITY | Magic::TIME | Magic::STATS_REPLY | Magic::METADATA | Magic::CONTROL | Magic::VOLUME),
+        }
+    }
+
+    fn recv_buffer_from(&self) -> Result<(Vec<u8>, PeerId, TimestampMicros), io::Error> {
         let mut buffer = vec![0u8; bark_protocol::packet::MAX_PACKET_SIZE];
 
-        let (nbytes, peer) = self.socket.recv_from(&mut buffer)?;
+        let (nbytes, peer, timestamp) = self.socket.recv_from(&mut buffer)?;
 
         // shrink vec to what we just read:
         assert!(nbytes <= buffer.len());
         buffer.resize(nbytes, 0);
 
-        let buffer = PacketBuffer::from_raw(buffer);
-
-        Ok((buffer, peer))
+        Ok((buffer, peer, timestamp))
     }
 
     pub fn recv_from(&self) -> Result<(Packet, PeerId), io::Error> {
+        let (packet, peer, _) = self.recv_timestamped()?;
+        Ok((packet, peer))
+    }
+
+    /// As [`ProtocolSocket::recv_from`], also returning the time the packet
+    /// was received, for packets used to sync clocks
+    pub fn recv_timestamped(&self) -> Result<(Packet, PeerId, TimestampMicros), io::Error> {
         loop {
-            let (buffer, peer) = self.recv_buffer_from()?;
+            let (mut buffer, peer, timestamp) = self.recv_buffer_from()?;
+
+            if let Some(auth) = &self.auth {
+                // packet header is always sent in the clear, peek at it to
+                // see whether we should expect this packet to be encrypted
+                let sealed = buffer.get(0..size_of::<PacketHeader>())
+                    .map(bytemuck::pod_read_unaligned::<PacketHeader>)
+                    .map(|header| self.is_sealed(header.magic))
+                    .unwrap_or_default();
 
-            if let Some(packet) = Packet::from_buffer(buffer) {
-                return Ok((packet, peer));
+                if let Err(e) = auth.verify(&mut buffer, sealed) {
+                    log::debug!("dropping packet from {peer}: {e}");
+
+                    self.warn_once(peer, Warning::Unauthenticated, || {
+                        log::warn!("dropping packets from peer {peer} which failed authentication: {e}");
+                    });
+
+                    continue;
+                }
             }
+
+            let Some(packet) = Packet::from_buffer(PacketBuffer::from_raw(buffer)) else {
+                continue;
+            };
+
+            if !self.check_version(&packet, peer) {
+                continue;
+            }
+
+            return Ok((packet, peer, timestamp));
+        }
+    }
+
+    fn check_version(&self, packet: &Packet, peer: PeerId) -> bool {
+        let version = packet.version();
+
+        let reason = match version.compatibility() {
+            Compatibility::Compatible => { return true; }
+            Compatibility::TooOld => "is running an older version of bark",
+            Compatibility::TooNew => "is running a newer version of bark",
+        };
+
+        self.warn_once(peer, Warning::IncompatibleVersion, || {
+            log::warn!("ignoring packets from peer {peer}: {reason} \
+                (peer protocol {version}, we speak {}, minimum supported {})",
+                bark_protocol::PROTOCOL_VERSION,
+                bark_protocol::MIN_PROTOCOL_VERSION);
+        });
+
+        false
+    }
+
+    fn warn_once(&self, peer: PeerId, warning: Warning, f: impl FnOnce()) {
+        let mut warned_peers = self.warned_peers.lock().unwrap();
+
+        if warned_peers.len() >= MAX_WARNED_PEERS && !warned_peers.contains(&(peer, warning)) {
+            warned_peers.clear();
+        }
+
+        let newly_seen = warned_peers.insert((peer, warning));
+        drop(warned_peers);
+
+        if newly_seen {
+            f();
         }
     }
 }
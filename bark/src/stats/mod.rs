@@ -10,9 +10,9 @@ use structopt::StructOpt;
 use termcolor::BufferedStandardStream;
 
 use bark_protocol::packet::{StatsRequest, StatsReply, PacketKind};
-use bark_protocol::types::StatsReplyFlags;
+use bark_protocol::types::{MetadataPacket, SessionId, StatsReplyFlags};
 
-use crate::socket::{Socket, SocketOpt, PeerId, ProtocolSocket};
+use crate::socket::{SocketOpt, PeerId, ProtocolSocket};
 use crate::RunError;
 
 use self::render::Padding;
@@ -24,10 +24,10 @@ pub struct StatsOpt {
 }
 
 pub fn run(opt: StatsOpt) -> Result<(), RunError> {
-    let socket = Socket::open(opt.socket)
+    let protocol = ProtocolSocket::open(opt.socket)
         .map_err(RunError::Listen)?;
 
-    let protocol = Arc::new(ProtocolSocket::new(socket));
+    let protocol = Arc::new(protocol);
 
     // spawn poller thread
     std::thread::spawn({
@@ -44,12 +44,18 @@ pub fn run(opt: StatsOpt) -> Result<(), RunError> {
     });
 
     let mut stats = HashMap::<PeerId, Entry>::new();
+    let mut metadata = HashMap::<SessionId, MetadataPacket>::new();
 
     loop {
-        let (reply, peer) = protocol.recv_from().map_err(RunError::Receive)?;
+        let (packet, peer) = protocol.recv_from().map_err(RunError::Receive)?;
 
-        let Some(PacketKind::StatsReply(reply)) = reply.parse() else {
-            continue;
+        let reply = match packet.parse() {
+            Some(PacketKind::StatsReply(reply)) => reply,
+            Some(PacketKind::Metadata(packet)) => {
+                metadata.insert(packet.data().sid, *packet.data());
+                continue;
+            }
+            _ => { continue; }
         };
 
         let prev_entries = stats.len();
@@ -58,6 +64,9 @@ pub fn run(opt: StatsOpt) -> Result<(), RunError> {
         stats.insert(peer, Entry { time: now, reply });
         stats.retain(|_, ent| ent.valid_at(now));
 
+        // forget metadata for streams no longer responding to stats requests
+        metadata.retain(|sid, _| stats.values().any(|ent| ent.reply.data().sid == *sid));
+
         let current_entries = stats.len();
 
         let mut out = BufferedStandardStream::stdout(termcolor::ColorChoice::Auto);
@@ -65,9 +74,10 @@ pub fn run(opt: StatsOpt) -> Result<(), RunError> {
         // move cursor up:
         move_cursor_up(&mut out, prev_entries);
 
-        // write stats for stream sources first
+        // group nodes by zone, and write stats for each zone's stream
+        // sources first
         let mut stats = stats.iter().collect::<Vec<_>>();
-        stats.sort_by_key(|(peer, entry)| (entry.is_receiver(), *peer));
+        stats.sort_by_key(|(peer, entry)| (entry.reply.data().zone(), entry.is_receiver(), *peer));
 
         let mut padding = Padding::default();
 
@@ -78,7 +88,8 @@ pub fn run(opt: StatsOpt) -> Result<(), RunError> {
         for (peer, entry) in &stats {
             // kill line
             kill_line(&mut out);
-            render::line(&mut out, &padding, &entry.reply, **peer);
+            let metadata = metadata.get(&entry.reply.data().sid);
+            render::line(&mut out, &padding, &entry.reply, metadata, **peer);
             new_line(&mut out);
         }
 
@@ -95,17 +106,17 @@ pub fn run(opt: StatsOpt) -> Result<(), RunError> {
     }
 }
 
-fn move_cursor_up(out: &mut BufferedStandardStream, lines: usize) {
+pub fn move_cursor_up(out: &mut BufferedStandardStream, lines: usize) {
     if lines > 0 {
         let _ = write!(out, "\x1b[{lines}F");
     }
 }
 
-fn kill_line(out: &mut BufferedStandardStream) {
+pub fn kill_line(out: &mut BufferedStandardStream) {
     let _ = write!(out, "\x1b[2K\r");
 }
 
-fn new_line(out: &mut BufferedStandardStream) {
+pub fn new_line(out: &mut BufferedStandardStream) {
     let _ = write!(out, "\n");
 }
 
@@ -16,6 +16,10 @@ pub fn display(stats: &NodeStats) -> String {
     format!("{username}@{hostname}")
 }
 
+pub fn hostname(stats: &NodeStats) -> &str {
+    from_fixed(&stats.hostname)
+}
+
 fn from_fixed(bytes: &[u8]) -> &str {
     let len = bytes.iter()
         .position(|b| *b == 0)
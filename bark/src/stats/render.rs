@@ -1,7 +1,8 @@
 use termcolor::{WriteColor, ColorSpec, Color};
 
 use bark_protocol::packet::StatsReply;
-use bark_protocol::types::{StatsReplyPacket, StatsReplyFlags};
+use bark_protocol::types::{MetadataPacket, StatsReplyPacket, StatsReplyFlags, ProtocolVersion};
+use bark_protocol::types::stats::clock::ClockStats;
 use bark_protocol::types::stats::receiver::{ReceiverStats, StreamStatus};
 use bark_protocol::types::stats::node::NodeStats;
 
@@ -10,32 +11,75 @@ use super::node;
 
 #[derive(Default)]
 pub struct Padding {
+    zone_width: usize,
     node_width: usize,
     peer_width: usize,
 }
 
 pub fn calculate(padding: &mut Padding, stats: &StatsReplyPacket, peer: PeerId) {
+    let zone_width = zone_name(stats.zone()).len();
     let node_width = node::display(&stats.node).len();
     let peer_width = peer.to_string().len();
 
+    padding.zone_width = std::cmp::max(padding.zone_width, zone_width);
     padding.node_width = std::cmp::max(padding.node_width, node_width);
     padding.peer_width = std::cmp::max(padding.peer_width, peer_width);
 }
 
-pub fn line(out: &mut dyn WriteColor, padding: &Padding, stats: &StatsReply, peer: PeerId) {
+pub fn line(out: &mut dyn WriteColor, padding: &Padding, stats: &StatsReply, metadata: Option<&MetadataPacket>, peer: PeerId) {
+    zone(out, padding, stats.data().zone());
     node(out, padding, &stats.data().node, peer);
+    version(out, stats.as_packet().version());
 
     if stats.flags().contains(StatsReplyFlags::IS_RECEIVER) {
-        receiver(out, &stats.data().receiver);
+        receiver(out, &stats.data().receiver, &stats.data().clock);
     } else if stats.flags().contains(StatsReplyFlags::IS_STREAM) {
         let _ = out.set_color(&ColorSpec::new()
             .set_fg(Some(Color::White))
             .set_bold(true));
         let _ = write!(out, "stream source");
         let _ = out.set_color(&ColorSpec::new());
+
+        if let Some(metadata) = metadata {
+            stream_metadata(out, metadata);
+        }
+    }
+}
+
+fn stream_metadata(out: &mut dyn WriteColor, metadata: &MetadataPacket) {
+    if !metadata.name().is_empty() {
+        let _ = write!(out, "  {}", metadata.name());
+    }
+
+    let _ = out.set_color(ColorSpec::new()
+        .set_dimmed(true));
+
+    let _ = write!(out, "  {} {}", metadata.format, metadata.audio_format());
+
+    let _ = out.set_color(&ColorSpec::new());
+
+    if !metadata.now_playing().is_empty() {
+        let _ = write!(out, "  {}", metadata.now_playing());
+    }
+}
+
+fn zone_name(zone: &str) -> &str {
+    if zone.is_empty() {
+        "(default)"
+    } else {
+        zone
     }
 }
 
+fn zone(out: &mut dyn WriteColor, padding: &Padding, zone: &str) {
+    let _ = out.set_color(ColorSpec::new()
+        .set_fg(Some(Color::Magenta)));
+
+    let _ = write!(out, "{:<width$}  ", zone_name(zone), width = padding.zone_width);
+
+    let _ = out.set_color(&ColorSpec::new());
+}
+
 fn node(out: &mut dyn WriteColor, padding: &Padding, node: &NodeStats, peer: PeerId) {
     let _ = out.set_color(&ColorSpec::new()
         .set_fg(Some(Color::Blue))
@@ -51,7 +95,23 @@ fn node(out: &mut dyn WriteColor, padding: &Padding, node: &NodeStats, peer: Pee
     let _ = out.set_color(&ColorSpec::new());
 }
 
-fn receiver(out: &mut dyn WriteColor, stats: &ReceiverStats) {
+fn version(out: &mut dyn WriteColor, version: ProtocolVersion) {
+    let mut spec = ColorSpec::new();
+
+    if version == bark_protocol::PROTOCOL_VERSION {
+        spec.set_dimmed(true);
+    } else {
+        // still compatible (or we wouldn't have parsed the reply), but
+        // highlight nodes that aren't running the same protocol as us
+        spec.set_fg(Some(Color::Yellow));
+    }
+
+    let _ = out.set_color(&spec);
+    let _ = write!(out, "{:<4}  ", version.to_string());
+    let _ = out.set_color(&ColorSpec::new());
+}
+
+fn receiver(out: &mut dyn WriteColor, stats: &ReceiverStats, clock: &ClockStats) {
     stream_status(out, stats.stream());
 
     time_field(out, "Audio", stats.audio_latency());
@@ -59,6 +119,11 @@ fn receiver(out: &mut dyn WriteColor, stats: &ReceiverStats) {
     time_field(out, "Output", stats.output_latency());
     time_field(out, "Network", stats.network_latency());
     time_field(out, "Predict", stats.predict_offset());
+    drift_field(out, clock.drift());
+    time_field(out, "Converge", clock.converge());
+    let _ = write!(out, "  Resync:[{:>3}]", stats.resyncs());
+    let _ = write!(out, "  Reject:[{:>5}]", clock.rejected());
+    volume_field(out, stats);
 }
 
 fn stream_status(out: &mut dyn WriteColor, stream: Option<StreamStatus>) {
@@ -98,6 +163,15 @@ fn indicator_style(value: Option<StreamStatus>) -> (ColorSpec, &'static str) {
                 .set_bold(true)
                 .set_intense(true);
         }
+        Some(StreamStatus::Paused) => {
+            text = "PAUS";
+            spec.set_fg(Some(Color::Blue))
+                .set_bold(true);
+        }
+        Some(StreamStatus::Idle) => {
+            text = "IDLE";
+            spec.set_dimmed(true);
+        }
         None => {
             text = "    ";
         }
@@ -106,6 +180,30 @@ fn indicator_style(value: Option<StreamStatus>) -> (ColorSpec, &'static str) {
     (spec, text)
 }
 
+fn volume_field(out: &mut dyn WriteColor, stats: &ReceiverStats) {
+    match stats.volume() {
+        Some(_) if stats.muted() => {
+            let _ = out.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+            let _ = write!(out, "  Vol:[mute]");
+            let _ = out.set_color(&ColorSpec::new());
+        }
+        Some(volume) => {
+            let _ = write!(out, "  Vol:[{volume:>3}%]");
+        }
+        None => {
+            let _ = write!(out, "  Vol:[    ]");
+        }
+    }
+}
+
+fn drift_field(out: &mut dyn WriteColor, value: Option<f64>) {
+    if let Some(ppm) = value {
+        let _ = write!(out, "  Drift:[{ppm:>+7.2} ppm]");
+    } else {
+        let _ = write!(out, "  Drift:[        ppm]");
+    }
+}
+
 fn time_field(out: &mut dyn WriteColor, name: &str, value: Option<f64>) {
     if let Some(secs) = value {
         let _ = write!(out, "  {name}:[{:>8.3} ms]", secs * 1000.0);
@@ -1,24 +1,30 @@
-use std::sync::Arc;
-use std::time::Duration;
-
-use bark_core::audio::Frame;
-use bark_core::encode::Encode;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bark_core::audio::PacketSamples;
+use bark_core::consts::{MAX_PARITY_GROUP, MAX_QUEUED_DECODE_SEGMENTS};
+use bark_core::parity::ParityEncoder;
+use bark_core::encode::{Encode, NewEncoderError};
 use bark_core::encode::pcm::{S16LEEncoder, F32LEEncoder};
-use bark_protocol::FRAMES_PER_PACKET;
-use bytemuck::Zeroable;
+use bark_protocol::{AudioFormat, ChannelCount, SampleRate, MAX_SAMPLES_PER_PACKET};
+use nix::sys::signal::{SigSet, Signal};
 use structopt::StructOpt;
 
 #[cfg(feature = "opus")]
 use bark_core::encode::opus::OpusEncoder;
 
 use bark_protocol::time::SampleDuration;
-use bark_protocol::packet::{self, Audio, StatsReply, PacketKind};
-use bark_protocol::types::{TimestampMicros, AudioPacketHeader, SessionId, ReceiverId, TimePhase};
+use bark_protocol::packet::{self, Audio, Control, Metadata, StatsReply, PacketKind};
+use bark_protocol::types::{TimestampMicros, AudioPacketHeader, ControlAction, SessionId, StreamId, ReceiverId, TimePhase, ZoneId};
 
 use crate::audio::config::{DeviceOpt, DEFAULT_PERIOD, DEFAULT_BUFFER};
 use crate::audio::Input;
-use crate::socket::{Socket, SocketOpt, ProtocolSocket};
-use crate::{stats, time, config};
+use crate::now_playing::NowPlaying;
+use crate::socket::{SocketOpt, ProtocolSocket};
+use crate::{stats, takeover, time, config};
 use crate::RunError;
 
 #[derive(StructOpt)]
@@ -38,6 +44,20 @@ pub struct StreamOpt {
     #[structopt(long, env = "BARK_SOURCE_INPUT_BUFFER")]
     pub input_buffer: Option<u64>,
 
+    /// Sample rate of the stream in Hz [default: 48000]
+    #[structopt(long, env = "BARK_SOURCE_INPUT_SAMPLE_RATE")]
+    pub input_sample_rate: Option<u32>,
+
+    /// Number of audio channels in the stream [default: 2]
+    #[structopt(long, env = "BARK_SOURCE_INPUT_CHANNELS")]
+    pub input_channels: Option<u16>,
+
+    /// How the capture time of audio is found: "userspace" estimates it
+    /// from the device delay when we read, otherwise ALSA timestamps the
+    /// device on the clock given, "monotonic-raw", "monotonic" or "realtime"
+    #[structopt(long, env = "BARK_SOURCE_INPUT_TIMESTAMP", default_value = "monotonic-raw")]
+    pub input_timestamp: config::AudioTimestamp,
+
     #[structopt(
         long,
         env = "BARK_SOURCE_DELAY_MS",
@@ -51,9 +71,67 @@ pub struct StreamOpt {
         default_value = "f32le",
     )]
     pub format: config::Format,
+
+    /// Human readable name for this stream, shown by receivers and stats
+    #[structopt(long, env = "BARK_SOURCE_NAME")]
+    pub name: Option<String>,
+
+    /// Path to read the currently playing title from. If this is a FIFO,
+    /// each line written to it replaces the title, otherwise the file is
+    /// re-read periodically
+    #[structopt(long, env = "BARK_SOURCE_NOW_PLAYING")]
+    pub now_playing: Option<PathBuf>,
+
+    /// Send a parity packet after every group of this many audio packets,
+    /// allowing receivers to reconstruct one lost packet per group
+    #[structopt(long, env = "BARK_SOURCE_FEC_GROUP")]
+    pub fec_group: Option<u16>,
+
+    /// Zone to stream to. Streams in different zones can share a multicast
+    /// group without interfering with each other
+    #[structopt(long, env = "BARK_ZONE", default_value = "")]
+    pub zone: String,
+
+    /// Priority of this stream, used by the priority takeover policy.
+    /// Higher priority streams take over from lower priority ones
+    #[structopt(long, env = "BARK_SOURCE_PRIORITY", default_value = "0")]
+    pub priority: u16,
+
+    /// Which stream wins when a stream starts while another is already
+    /// playing in the same zone: newest, priority, or first
+    #[structopt(long, env = "BARK_TAKEOVER", default_value = "newest")]
+    pub takeover: config::Takeover,
+
+    /// Run as a hot standby: capture audio but stay silent while another
+    /// source is streaming, and carry on its stream if it goes silent
+    #[structopt(long)]
+    pub standby: bool,
+
+    /// Padding for time packets: none, or audio to pad them to the size of
+    /// audio packets, so that they see the same network delay
+    #[structopt(long, env = "BARK_SOURCE_TIME_PADDING", default_value = "none")]
+    pub time_padding: config::TimePadding,
 }
 
 pub fn run(opt: StreamOpt) -> Result<(), RunError> {
+    // block control signals before any other threads are started, so that
+    // only the signal thread ever receives them
+    let signals = handled_signals();
+    signals.thread_block().map_err(RunError::Signal)?;
+
+    let format = AudioFormat {
+        sample_rate: opt.input_sample_rate
+            .map(SampleRate)
+            .unwrap_or(bark_protocol::SAMPLE_RATE),
+        channels: opt.input_channels
+            .map(ChannelCount)
+            .unwrap_or(bark_protocol::CHANNELS),
+    };
+
+    if !format.is_supported() {
+        return Err(NewEncoderError::UnsupportedAudioFormat(format).into());
+    }
+
     let input = Input::new(DeviceOpt {
         device: opt.input_device,
         period: opt.input_period
@@ -62,45 +140,142 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
         buffer: opt.input_buffer
             .map(SampleDuration::from_frame_count)
             .unwrap_or(DEFAULT_BUFFER),
+        format,
+        timestamp: opt.input_timestamp,
     })?;
 
-    let socket = Socket::open(opt.socket)?;
+    // the stream carries whatever format the device actually captures at,
+    // which can differ from the one asked for
+    let format = input.format();
+
+    if !format.is_supported() {
+        return Err(NewEncoderError::UnsupportedAudioFormat(format).into());
+    }
 
-    let protocol = Arc::new(ProtocolSocket::new(socket));
+    if let Some(group) = opt.fec_group {
+        if group < 2 || usize::from(group) > MAX_PARITY_GROUP {
+            return Err(RunError::InvalidFecGroup(group));
+        }
+    }
+
+    let now_playing = opt.now_playing
+        .map(NowPlaying::open)
+        .transpose()
+        .map_err(RunError::OpenNowPlaying)?;
+
+    let protocol = Arc::new(ProtocolSocket::open(opt.socket)?);
 
     let delay = Duration::from_millis(opt.delay_ms);
     let delay = SampleDuration::from_std_duration_lossy(delay);
 
     let sid = generate_session_id();
     let node = stats::node::get();
+    let zone = ZoneId::from_name(&opt.zone);
 
     let mut encoder: Box<dyn Encode> = match opt.format {
         config::Format::S16LE => Box::new(S16LEEncoder),
         config::Format::F32LE => Box::new(F32LEEncoder),
         #[cfg(feature = "opus")]
-        config::Format::Opus => Box::new(OpusEncoder::new()?),
+        config::Format::Opus => Box::new(OpusEncoder::new(format)?),
     };
 
-    log::info!("instantiated encoder: {}", encoder);
+    log::info!("instantiated encoder: {} ({})", encoder, format);
 
     let mut audio_header = AudioPacketHeader {
         sid,
-        seq: 1,
-        pts: TimestampMicros(0),
-        dts: TimestampMicros(0),
+        stream: sid.into(),
+        seq: 1.into(),
+        pts: TimestampMicros::new(0),
+        dts: TimestampMicros::new(0),
         format: encoder.header_format(),
+        channels: format.channels.0.into(),
+        sample_rate: format.sample_rate.0.into(),
+        zone,
+        priority: opt.priority.into(),
+        _pad: [0; 2],
     };
 
+    // recently sent audio packets, kept around to answer retransmit
+    // requests from receivers. there's no point keeping more than a
+    // receiver can queue
+    let history = Arc::new(Mutex::new(VecDeque::<Audio>::with_capacity(MAX_QUEUED_DECODE_SEGMENTS)));
+
+    let preemption = Arc::new(Preemption::default());
+
+    let paused = Arc::new(AtomicBool::new(false));
+
+    // seq of the next audio packet to be sent
+    let next_seq = Arc::new(AtomicU64::new(audio_header.seq.get()));
+
+    // length of the last audio packet sent, for padding time packets to
+    let audio_length = Arc::new(AtomicUsize::new(0));
+
+    std::thread::spawn({
+        let protocol = Arc::clone(&protocol);
+        let paused = Arc::clone(&paused);
+        let next_seq = Arc::clone(&next_seq);
+        move || {
+            crate::thread::set_name("bark/signal");
+
+            loop {
+                match signals.wait() {
+                    Ok(Signal::SIGUSR1) => {
+                        log::info!("pausing stream");
+                        paused.store(true, Ordering::Relaxed);
+                    }
+                    Ok(Signal::SIGUSR2) => {
+                        log::info!("resuming stream");
+                        paused.store(false, Ordering::Relaxed);
+                    }
+                    Ok(signal @ (Signal::SIGINT | Signal::SIGTERM)) => {
+                        log::info!("received {signal}, ending stream");
+
+                        // control packets aren't acknowledged, so send a
+                        // few in case some are lost
+                        for _ in 0..3 {
+                            send_control(&protocol, sid, next_seq.load(Ordering::Relaxed), ControlAction::END);
+                        }
+
+                        std::process::exit(0);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("error waiting for signal: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let standby = opt.standby.then(|| {
+        log::info!("running as standby, waiting for a primary source");
+
+        Arc::new(Standby::new(opt.takeover, audio_header))
+    });
+
     std::thread::spawn({
         let protocol = protocol.clone();
+        let history = Arc::clone(&history);
+        let preemption = Arc::clone(&preemption);
+        let standby = standby.clone();
+        let paused = Arc::clone(&paused);
+        let next_seq = Arc::clone(&next_seq);
+        let audio_length = Arc::clone(&audio_length);
+        let mut parity = opt.fec_group.map(ParityEncoder::new);
+
         move || {
             crate::thread::set_name("bark/audio");
 
+            let mut preempted = false;
+            let mut last_pause: Option<Instant> = None;
+
             loop {
-                let mut audio_buffer = [Frame::zeroed(); FRAMES_PER_PACKET];
+                let mut audio_buffer: PacketSamples = [0.0; MAX_SAMPLES_PER_PACKET];
+                let audio_buffer = &mut audio_buffer[0..format.samples_per_packet()];
 
                 // read audio input
-                let timestamp = match input.read(&mut audio_buffer) {
+                let timestamp = match input.read(audio_buffer) {
                     Ok(ts) => ts,
                     Err(e) => {
                         log::error!("error reading audio input: {e}");
@@ -108,9 +283,62 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
                     }
                 };
 
+                // keep reading input while another stream has taken over,
+                // but don't send anything until it ends
+                if preemption.active() {
+                    if !preempted {
+                        // let receivers still playing our stream know that
+                        // it's over, rather than have them wait it out
+                        send_control(&protocol, sid, audio_header.seq.get(), ControlAction::END);
+                        preempted = true;
+                    }
+
+                    audio_header.seq.set(audio_header.seq.get() + 1);
+                    next_seq.store(audio_header.seq.get(), Ordering::Relaxed);
+                    continue;
+                }
+
+                if preempted {
+                    log::info!("resuming stream");
+                    preempted = false;
+                }
+
+                // while paused, keep telling receivers so. seq stays put, so
+                // receivers can carry on from where they left off
+                if paused.load(Ordering::Relaxed) {
+                    let due = last_pause
+                        .map(|sent| sent.elapsed() >= PAUSE_INTERVAL)
+                        .unwrap_or(true);
+
+                    if due {
+                        send_control(&protocol, sid, audio_header.seq.get(), ControlAction::PAUSE);
+                        last_pause = Some(Instant::now());
+                    }
+
+                    continue;
+                }
+
+                if last_pause.take().is_some() {
+                    send_control(&protocol, sid, audio_header.seq.get(), ControlAction::RESUME);
+                }
+
+                if let Some(standby) = &standby {
+                    match standby.role() {
+                        Role::Standby => continue,
+                        Role::Failover { stream, seq } => {
+                            log::warn!("primary source has gone silent, taking over stream sid={} at seq={seq}",
+                                stream.get());
+
+                            audio_header.stream = stream;
+                            audio_header.seq.set(seq);
+                        }
+                        Role::Active => {}
+                    }
+                }
+
                 // encode audio
                 let mut encode_buffer = [0; Audio::MAX_BUFFER_LENGTH];
-                let encoded_data = match encoder.encode_packet(&audio_buffer, &mut encode_buffer) {
+                let encoded_data = match encoder.encode_packet(audio_buffer, &mut encode_buffer) {
                     Ok(size) => &encode_buffer[0..size],
                     Err(e) => {
                         log::error!("error encoding audio: {e}");
@@ -133,9 +361,56 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
 
                 // send it
                 protocol.broadcast(audio.as_packet()).expect("broadcast");
+                audio_length.store(audio.as_packet().len(), Ordering::Relaxed);
+
+                // send parity packet if this completes a group
+                if let Some(parity) = parity.as_mut().and_then(|parity| parity.push(&audio)) {
+                    protocol.broadcast(parity.as_packet()).expect("broadcast parity");
+                }
+
+                // remember it in case it needs to be retransmitted
+                let mut history = history.lock().unwrap();
+                if history.len() == MAX_QUEUED_DECODE_SEGMENTS {
+                    history.pop_front();
+                }
+                history.push_back(audio);
+                drop(history);
 
                 // reset header for next packet:
-                audio_header.seq += 1;
+                audio_header.seq.set(audio_header.seq.get() + 1);
+                next_seq.store(audio_header.seq.get(), Ordering::Relaxed);
+            }
+        }
+    });
+
+    // set up metadata sender thread
+    std::thread::spawn({
+        let protocol = Arc::clone(&protocol);
+        let name = opt.name.unwrap_or_default();
+        let codec = audio_header.format;
+
+        move || {
+            crate::thread::set_name("bark/metadata");
+
+            let mut metadata = Metadata::allocate()
+                .expect("allocate Metadata packet");
+
+            let data = metadata.data_mut();
+            data.sid = sid;
+            data.delay = TimestampMicros::new(delay.to_std_duration_lossy().as_micros().try_into().unwrap());
+            data.format = codec;
+            data.set_audio_format(format);
+            data.set_name(&name);
+
+            loop {
+                if let Some(now_playing) = &now_playing {
+                    metadata.data_mut().set_now_playing(&now_playing.get());
+                }
+
+                protocol.broadcast(metadata.as_packet())
+                    .expect("broadcast metadata");
+
+                std::thread::sleep(Duration::from_millis(1000));
             }
         }
     });
@@ -146,19 +421,25 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
         crate::thread::set_realtime_priority();
 
         let protocol = Arc::clone(&protocol);
+        let preemption = Arc::clone(&preemption);
+        let standby = standby.clone();
+        let audio_length = Arc::clone(&audio_length);
+        let time_padding = opt.time_padding;
         move || {
-            let mut time = packet::Time::allocate()
-                .expect("allocate Time packet");
+            loop {
+                let standing_by = standby.as_ref()
+                    .is_some_and(|standby| !standby.is_active());
 
-            // set up packet
-            let data = time.data_mut();
-            data.sid = sid;
-            data.rid = ReceiverId::broadcast();
+                if preemption.active() || standing_by {
+                    std::thread::sleep(Duration::from_millis(200));
+                    continue;
+                }
 
-            loop {
-                time.data_mut().stream_1 = time::now();
+                let mut time = time_packet(sid, zone, ReceiverId::broadcast(), time_padding, &audio_length);
+                let sent = time::now();
+                time.data_mut().stream_1 = sent;
 
-                protocol.broadcast(time.as_packet())
+                protocol.broadcast_timestamped(time.as_packet(), sent)
                     .expect("broadcast time");
 
                 std::thread::sleep(Duration::from_millis(200));
@@ -170,15 +451,30 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
     crate::thread::set_realtime_priority();
 
     loop {
-        let (packet, peer) = protocol.recv_from().expect("protocol.recv_from");
+        let (packet, peer, received) = protocol.recv_timestamped().expect("protocol.recv_timestamped");
 
         match packet.parse() {
             Some(PacketKind::Audio(audio)) => {
-                // we should only ever receive an audio packet if another
-                // stream is present. check if it should take over
-                if audio.header().sid > sid {
-                    log::warn!("peer {peer} has taken over stream, exiting");
-                    break;
+                // apart from our own packets looping back, we should only
+                // ever receive an audio packet if another stream is
+                // present. streams in other zones don't concern us,
+                // otherwise check if it should take over
+                if audio.header().zone != zone || audio.header().sid == sid {
+                    continue;
+                }
+
+                // a standby watches the primary it is standing by for,
+                // which is never a takeover
+                if let Some(standby) = &standby {
+                    if standby.observe(audio.header()) {
+                        continue;
+                    }
+                }
+
+                if takeover::takes_over(opt.takeover, &audio_header, audio.header())
+                    && preemption.observe(audio.header().sid)
+                {
+                    log::warn!("peer {peer} has taken over stream, pausing until it ends");
                 }
             }
             Some(PacketKind::Time(mut time)) => {
@@ -188,8 +484,27 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
                 }
 
                 match time.data().phase() {
+                    Some(TimePhase::Request) => {
+                        // a receiver wants a sample right away, usually
+                        // because our stream has just begun for it. start
+                        // an exchange with it alone
+                        let rid = time.data().rid;
+                        let mut time = time_packet(sid, zone, rid, opt.time_padding, &audio_length);
+                        let sent = time::now();
+                        time.data_mut().stream_1 = sent;
+
+                        let _ = protocol.send_to_timestamped(time.as_packet(), peer, sent);
+                    }
                     Some(TimePhase::ReceiverReply) => {
-                        time.data_mut().stream_3 = time::now();
+                        let data = time.data_mut();
+
+                        // swap our own timestamp of sending the broadcast
+                        // for the kernel's, when we have it
+                        if let Some(sent) = protocol.tx_timestamp(data.stream_1) {
+                            data.stream_1 = sent;
+                        }
+
+                        data.stream_3 = received;
 
                         protocol.send_to(time.as_packet(), peer)
                             .expect("protocol.send_to responding to time packet");
@@ -202,7 +517,7 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
 
             }
             Some(PacketKind::StatsRequest(_)) => {
-                let reply = StatsReply::source(sid, node)
+                let reply = StatsReply::source(sid, &opt.zone, node)
                     .expect("allocate StatsReply packet");
 
                 let _ = protocol.send_to(reply.as_packet(), peer);
@@ -210,13 +525,270 @@ pub fn run(opt: StreamOpt) -> Result<(), RunError> {
             Some(PacketKind::StatsReply(_)) => {
                 // ignore
             }
+            Some(PacketKind::Metadata(_)) => {
+                // ignore
+            }
+            Some(PacketKind::Parity(_)) => {
+                // ignore
+            }
+            Some(PacketKind::Control(control)) => {
+                // the stream which has taken over ours may pause or end
+                // rather than fall silent
+                let data = control.data();
+
+                if preemption.observe_control(data.sid, data.action) {
+                    log::info!("stream that took over has ended");
+                }
+            }
+            Some(PacketKind::Volume(_)) => {
+                // ignore
+            }
+            Some(PacketKind::Retransmit(retransmit)) => {
+                if retransmit.data().sid != sid {
+                    continue;
+                }
+
+                let history = history.lock().unwrap();
+
+                let Some(first_seq) = history.front().map(|audio| audio.header().seq.get()) else {
+                    continue;
+                };
+
+                for seq in retransmit.data().seqs() {
+                    let Some(audio) = seq.checked_sub(first_seq)
+                        .and_then(|idx| history.get(idx as usize))
+                    else {
+                        log::debug!("peer {peer} requested retransmit of seq={seq}, no longer in history");
+                        continue;
+                    };
+
+                    let _ = protocol.send_to(audio.as_packet(), peer);
+                }
+            }
             None => {
                 // unknown packet, ignore
             }
         }
     }
+}
+
+/// How often a paused source reminds receivers that it is paused
+const PAUSE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of packets a primary source can miss before a standby takes over
+const FAILOVER_PACKETS: u32 = 4;
+
+/// Tracks whether another stream has taken over from ours. While it keeps
+/// sending audio, or keeps reminding receivers that it is paused, we stay
+/// quiet. Receivers hold on to a paused stream too, so resuming over it
+/// would only go unheard. We resume once it ends, or once it has been
+/// silent for [`takeover::STREAM_TIMEOUT`]
+#[derive(Default)]
+struct Preemption {
+    // session of the stream that took over, and when we last heard from it
+    by: Mutex<Option<(SessionId, Instant)>>,
+}
+
+impl Preemption {
+    /// Records audio from a stream that takes over ours, returning true if
+    /// we were not already preempted
+    fn observe(&self, sid: SessionId) -> bool {
+        let mut by = self.by.lock().unwrap();
+        let was_active = is_preempting(&by);
+        *by = Some((sid, Instant::now()));
+        !was_active
+    }
+
+    /// Records a control packet, returning true if it ended the stream
+    /// that took over ours
+    fn observe_control(&self, sid: SessionId, action: ControlAction) -> bool {
+        let mut by = self.by.lock().unwrap();
+
+        if !is_preempting(&by) || by.is_some_and(|(by_sid, _)| by_sid != sid) {
+            return false;
+        }
+
+        if action == ControlAction::END {
+            *by = None;
+            return true;
+        }
+
+        // paused or resuming, either way still there
+        *by = Some((sid, Instant::now()));
+        false
+    }
+
+    fn active(&self) -> bool {
+        is_preempting(&self.by.lock().unwrap())
+    }
+}
+
+fn is_preempting(by: &Option<(SessionId, Instant)>) -> bool {
+    by.is_some_and(|(_, last_seen)| last_seen.elapsed() < takeover::STREAM_TIMEOUT)
+}
+
+#[derive(Clone, Copy)]
+struct Primary {
+    stream: StreamId,
+    seq: u64,
+    last_seen: Instant,
+}
+
+/// What a standby source should be doing with the packet it is about to send
+enum Role {
+    /// Primary is still live, stay silent
+    Standby,
+    /// Primary has just gone silent, carry on its stream from `seq`
+    Failover { stream: StreamId, seq: u64 },
+    /// Already streaming in place of the primary
+    Active,
+}
+
+/// State of a hot standby source. Watches audio from a primary source and
+/// takes over its stream when the primary misses [`FAILOVER_PACKETS`]
+/// packets in a row, continuing its stream id and sequence numbers so that
+/// receivers keep their queue
+struct Standby {
+    takeover: config::Takeover,
+    header: AudioPacketHeader,
+    timeout: Duration,
+    state: Mutex<StandbyState>,
+}
 
-    Ok(())
+struct StandbyState {
+    primary: Option<Primary>,
+    active: bool,
+}
+
+impl Standby {
+    fn new(takeover: config::Takeover, header: AudioPacketHeader) -> Self {
+        let packet_duration = header.audio_format().packet_duration().to_std_duration_lossy();
+
+        Standby {
+            takeover,
+            header,
+            timeout: packet_duration * FAILOVER_PACKETS,
+            state: Mutex::new(StandbyState { primary: None, active: false }),
+        }
+    }
+
+    /// Records an audio packet from another source, returning true if it
+    /// came from the primary we are standing by for
+    fn observe(&self, header: &AudioPacketHeader) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let follow = match state.primary {
+            None => true,
+            Some(primary) if primary.stream == header.stream => true,
+            Some(primary) if primary.last_seen.elapsed() >= self.timeout => {
+                // the primary is gone. if we have taken over from it, a new
+                // stream only becomes our primary if it would take over
+                // from us anyway
+                let current = AudioPacketHeader { stream: primary.stream, ..self.header };
+                !state.active || takeover::takes_over(self.takeover, &current, header)
+            }
+            Some(_) => false,
+        };
+
+        if !follow {
+            return false;
+        }
+
+        if state.primary.map(|primary| primary.stream) != Some(header.stream) {
+            log::info!("standing by for stream sid={}", header.stream.get());
+        }
+
+        state.primary = Some(Primary {
+            stream: header.stream,
+            seq: header.seq.get(),
+            last_seen: Instant::now(),
+        });
+
+        true
+    }
+
+    fn role(&self) -> Role {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(primary) = state.primary else {
+            return Role::Standby;
+        };
+
+        let silence = primary.last_seen.elapsed();
+
+        if silence < self.timeout {
+            if state.active {
+                log::info!("primary source is back, standing by");
+                state.active = false;
+            }
+
+            return Role::Standby;
+        }
+
+        if state.active {
+            return Role::Active;
+        }
+
+        state.active = true;
+
+        // skip the seqs the primary would have sent while silent, so our
+        // packets line up in time with what it had already sent
+        let packet_duration = self.timeout / FAILOVER_PACKETS;
+        let missed = silence.as_micros() / packet_duration.as_micros();
+
+        Role::Failover {
+            stream: primary.stream,
+            seq: primary.seq + 1 + u64::try_from(missed).unwrap(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.state.lock().unwrap().active
+    }
+}
+
+/// Signals handled by the signal thread: SIGUSR1 pauses the stream, SIGUSR2
+/// resumes it, and SIGINT and SIGTERM end it cleanly
+fn handled_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGUSR1);
+    signals.add(Signal::SIGUSR2);
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals
+}
+
+/// Allocates a time packet to start an exchange with `rid`. Audio packet
+/// size can vary from one packet to the next, so each time packet is sized
+/// afresh
+fn time_packet(
+    sid: SessionId,
+    zone: ZoneId,
+    rid: ReceiverId,
+    padding: config::TimePadding,
+    audio_length: &AtomicUsize,
+) -> packet::Time {
+    let length = match padding {
+        config::TimePadding::None => 0,
+        config::TimePadding::Audio => audio_length.load(Ordering::Relaxed),
+    };
+
+    let mut time = packet::Time::allocate_padded(length)
+        .expect("allocate Time packet");
+
+    let data = time.data_mut();
+    data.sid = sid;
+    data.rid = rid;
+    data.zone = zone;
+    time
+}
+
+fn send_control(protocol: &ProtocolSocket, sid: SessionId, seq: u64, action: ControlAction) {
+    let control = Control::new(sid, seq, action)
+        .expect("allocate Control packet");
+
+    protocol.broadcast(control.as_packet())
+        .expect("broadcast control");
 }
 
 fn generate_session_id() -> SessionId {
@@ -225,5 +797,73 @@ fn generate_session_id() -> SessionId {
     let timespec = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME)
         .expect("clock_gettime(CLOCK_REALTIME)");
 
-    SessionId(timespec.num_microseconds())
+    SessionId::new(timespec.num_microseconds())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OURS: SessionId = SessionId::new(1);
+    const OTHER: SessionId = SessionId::new(2);
+
+    #[test]
+    fn preempted_while_other_stream_sends_audio() {
+        let preemption = Preemption::default();
+        assert!(!preemption.active());
+
+        assert!(preemption.observe(OTHER));
+        assert!(!preemption.observe(OTHER));
+        assert!(preemption.active());
+    }
+
+    #[test]
+    fn preemption_lapses_once_other_stream_falls_silent() {
+        let preemption = Preemption::default();
+        preemption.observe(OTHER);
+
+        let last_seen = Instant::now() - takeover::STREAM_TIMEOUT;
+        *preemption.by.lock().unwrap() = Some((OTHER, last_seen));
+        assert!(!preemption.active());
+
+        // a late pause from it doesn't bring it back
+        assert!(!preemption.observe_control(OTHER, ControlAction::PAUSE));
+        assert!(!preemption.active());
+    }
+
+    #[test]
+    fn paused_stream_stays_preempting() {
+        let preemption = Preemption::default();
+        preemption.observe(OTHER);
+
+        // the other stream pauses, and keeps saying so until well past the
+        // time it would have timed out had it fallen silent
+        let last_seen = Instant::now() - takeover::STREAM_TIMEOUT / 2;
+        *preemption.by.lock().unwrap() = Some((OTHER, last_seen));
+
+        assert!(!preemption.observe_control(OTHER, ControlAction::PAUSE));
+
+        let (_, last_seen) = preemption.by.lock().unwrap().unwrap();
+        assert!(last_seen.elapsed() < takeover::STREAM_TIMEOUT / 2);
+        assert!(preemption.active());
+    }
+
+    #[test]
+    fn ending_stream_lifts_preemption() {
+        let preemption = Preemption::default();
+        preemption.observe(OTHER);
+
+        assert!(preemption.observe_control(OTHER, ControlAction::END));
+        assert!(!preemption.active());
+    }
+
+    #[test]
+    fn control_from_other_streams_ignored() {
+        let preemption = Preemption::default();
+        preemption.observe(OTHER);
+
+        assert!(!preemption.observe_control(OURS, ControlAction::END));
+        assert!(!preemption.observe_control(SessionId::new(3), ControlAction::END));
+        assert!(preemption.active());
+    }
 }
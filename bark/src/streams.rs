@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+use termcolor::{BufferedStandardStream, ColorSpec, WriteColor};
+
+use bark_protocol::packet::{Audio, PacketKind, StatsRequest};
+use bark_protocol::types::stats::node::NodeStats;
+use bark_protocol::types::{AudioPacketFormat, SessionId, StatsReplyFlags};
+use bark_protocol::AudioFormat;
+
+use crate::socket::{PeerId, ProtocolSocket, SocketOpt};
+use crate::stats::{self, kill_line, move_cursor_up, new_line};
+use crate::RunError;
+
+const RENDER_INTERVAL: Duration = Duration::from_millis(1000);
+const SESSION_TIMEOUT: Duration = Duration::from_millis(2000);
+
+#[derive(StructOpt)]
+pub struct StreamsOpt {
+    #[structopt(flatten)]
+    pub socket: SocketOpt,
+}
+
+pub fn run(opt: StreamsOpt) -> Result<(), RunError> {
+    let protocol = ProtocolSocket::open(opt.socket)
+        .map_err(RunError::Listen)?;
+
+    let protocol = Arc::new(protocol);
+
+    // ask stream sources to identify themselves
+    std::thread::spawn({
+        let protocol = Arc::clone(&protocol);
+        move || {
+            let request = StatsRequest::new()
+                .expect("allocate StatsRequest packet");
+
+            loop {
+                let _ = protocol.broadcast(request.as_packet());
+                std::thread::sleep(RENDER_INTERVAL);
+            }
+        }
+    });
+
+    let sessions = Arc::new(Mutex::new(HashMap::<SessionId, Session>::new()));
+
+    // redraw on a tick of its own rather than as packets arrive, so that
+    // streams which have gone quiet still expire once nothing is arriving
+    std::thread::spawn({
+        let sessions = Arc::clone(&sessions);
+        move || {
+            let mut last_render = Instant::now();
+            let mut prev_lines = 0;
+
+            loop {
+                std::thread::sleep(RENDER_INTERVAL);
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(last_render);
+                last_render = now;
+
+                let mut sessions = sessions.lock().unwrap();
+
+                sessions.retain(|_, session| now.duration_since(session.last_seen) < SESSION_TIMEOUT);
+
+                let mut rows = sessions.iter_mut()
+                    .map(|(sid, session)| session.row(*sid, elapsed))
+                    .collect::<Vec<_>>();
+
+                drop(sessions);
+
+                rows.sort();
+
+                prev_lines = render(&rows, prev_lines);
+            }
+        }
+    });
+
+    loop {
+        let (packet, peer) = protocol.recv_from().map_err(RunError::Receive)?;
+
+        match packet.parse() {
+            Some(PacketKind::Audio(audio)) => {
+                let sid = audio.header().sid;
+
+                sessions.lock().unwrap()
+                    .entry(sid)
+                    .or_insert_with(|| Session::new(peer))
+                    .observe(&audio);
+            }
+            Some(PacketKind::StatsReply(reply)) => {
+                if !reply.flags().contains(StatsReplyFlags::IS_STREAM) {
+                    continue;
+                }
+
+                if let Some(session) = sessions.lock().unwrap().get_mut(&reply.data().sid) {
+                    session.node = Some(reply.data().node);
+                    session.zone = reply.data().zone().to_owned();
+                }
+            }
+            Some(PacketKind::Metadata(metadata)) => {
+                if let Some(session) = sessions.lock().unwrap().get_mut(&metadata.data().sid) {
+                    session.name = metadata.data().name().to_owned();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Session {
+    peer: PeerId,
+    node: Option<NodeStats>,
+    zone: String,
+    name: String,
+    codec: AudioPacketFormat,
+    format: AudioFormat,
+    delay: Duration,
+    last_seen: Instant,
+    // observations since last render, reset each time the table is drawn
+    window: Window,
+}
+
+#[derive(Default)]
+struct Window {
+    packets: u64,
+    first_seq: Option<u64>,
+    last_seq: u64,
+}
+
+impl Session {
+    fn new(peer: PeerId) -> Self {
+        Session {
+            peer,
+            node: None,
+            zone: String::new(),
+            name: String::new(),
+            codec: AudioPacketFormat::F32LE,
+            format: AudioFormat::DEFAULT,
+            delay: Duration::ZERO,
+            last_seen: Instant::now(),
+            window: Window::default(),
+        }
+    }
+
+    fn observe(&mut self, audio: &Audio) {
+        let header = audio.header();
+        let seq = header.seq.get();
+
+        self.codec = header.format;
+        self.format = header.audio_format();
+        self.delay = Duration::from_micros(header.pts.get().saturating_sub(header.dts.get()));
+        self.last_seen = Instant::now();
+
+        self.window.packets += 1;
+        self.window.first_seq.get_or_insert(seq);
+        self.window.last_seq = std::cmp::max(self.window.last_seq, seq);
+    }
+
+    fn row(&mut self, sid: SessionId, elapsed: Duration) -> Vec<String> {
+        let window = std::mem::take(&mut self.window);
+
+        let rate = window.packets as f64 / elapsed.as_secs_f64();
+
+        // packets we should have seen in this window, judging by seq
+        let expected = window.first_seq
+            .map(|first| window.last_seq + 1 - first)
+            .unwrap_or(0);
+
+        let loss = if expected > 0 {
+            let lost = expected.saturating_sub(window.packets);
+            format!("{:.1}%", lost as f64 * 100.0 / expected as f64)
+        } else {
+            "-".to_owned()
+        };
+
+        let source = self.node.as_ref()
+            .map(stats::node::display)
+            .unwrap_or_else(|| "?".to_owned());
+
+        let zone = if self.zone.is_empty() { "(default)" } else { &self.zone };
+
+        vec![
+            zone.to_owned(),
+            sid.get().to_string(),
+            source,
+            self.peer.to_string(),
+            format!("{} {}", self.codec, self.format),
+            format!("{rate:.0} pkt/s"),
+            format!("{loss} loss"),
+            format!("{}ms delay", self.delay.as_millis()),
+            self.name.clone(),
+        ]
+    }
+}
+
+fn render(rows: &[Vec<String>], prev_lines: usize) -> usize {
+    let mut out = BufferedStandardStream::stdout(termcolor::ColorChoice::Auto);
+
+    move_cursor_up(&mut out, prev_lines);
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+
+    let widths = (0..columns)
+        .map(|col| rows.iter().map(|row| row[col].len()).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    for row in rows {
+        kill_line(&mut out);
+
+        for (col, (field, width)) in row.iter().zip(&widths).enumerate() {
+            // dim the less important columns
+            let dimmed = col == 1 || col == 3;
+            let _ = out.set_color(ColorSpec::new().set_dimmed(dimmed));
+            let _ = write!(out, "{field:<width$}  ");
+        }
+
+        let _ = out.set_color(&ColorSpec::new());
+        new_line(&mut out);
+    }
+
+    if rows.len() < prev_lines {
+        let remove_lines = prev_lines - rows.len();
+        for _ in 0..remove_lines {
+            kill_line(&mut out);
+            new_line(&mut out);
+        }
+        move_cursor_up(&mut out, remove_lines);
+    }
+
+    let _ = out.flush();
+
+    rows.len()
+}
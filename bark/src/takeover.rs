@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use bark_protocol::types::AudioPacketHeader;
+
+use crate::config::Takeover;
+
+/// How long a stream can go without sending audio before it is considered
+/// ended, letting a stream it was holding off take over
+pub const STREAM_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Decides whether the stream `candidate` should take over from the stream
+/// `current` while both are live. Sources and receivers both use this, so
+/// that a source stops sending exactly when receivers stop listening to it.
+pub fn takes_over(policy: Takeover, current: &AudioPacketHeader, candidate: &AudioPacketHeader) -> bool {
+    match policy {
+        Takeover::Newest => candidate.stream > current.stream,
+        Takeover::Priority => {
+            (candidate.priority.get(), candidate.stream) > (current.priority.get(), current.stream)
+        }
+        Takeover::First => candidate.stream < current.stream,
+    }
+}
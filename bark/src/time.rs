@@ -1,4 +1,4 @@
-use nix::sys::time::TimeValLike;
+use nix::sys::time::{TimeSpec, TimeValLike};
 use nix::time::ClockId;
 
 use bark_protocol::types::TimestampMicros;
@@ -10,5 +10,84 @@ pub fn now() -> TimestampMicros {
     let micros = u64::try_from(timespec.num_microseconds())
         .expect("cannot convert i64 time value to u64");
 
-    TimestampMicros(micros)
+    TimestampMicros::new(micros)
+}
+
+/// Converts a recent timestamp taken on CLOCK_REALTIME, as the kernel's
+/// socket timestamps are, to the CLOCK_MONOTONIC_RAW timescale of [`now`].
+/// The two clocks are only compared at the time of the call, so this should
+/// be done as soon as possible after the timestamp is taken
+pub fn from_realtime(timestamp: TimeSpec) -> TimestampMicros {
+    from_clock(ClockId::CLOCK_REALTIME, timestamp)
+}
+
+/// How far CLOCK_REALTIME is ahead of the CLOCK_MONOTONIC_RAW timescale of
+/// [`now`] as of the call, in microseconds
+pub fn realtime_offset() -> i64 {
+    let realtime = nix::time::clock_gettime(ClockId::CLOCK_REALTIME)
+        .expect("clock_gettime(CLOCK_REALTIME) failed");
+
+    let monotonic = i64::try_from(now().get())
+        .expect("cannot convert u64 time value to i64");
+
+    realtime.num_microseconds() - monotonic
+}
+
+/// Converts a timestamp taken on CLOCK_REALTIME to the timescale of [`now`]
+/// using `offset`, from [`realtime_offset`] called around when the timestamp
+/// was taken. For timestamps which may only be converted some time later,
+/// where [`from_realtime`] would take in any step of the realtime clock in
+/// the meantime
+pub fn from_realtime_offset(offset: i64, timestamp: TimeSpec) -> TimestampMicros {
+    let micros = timestamp.num_microseconds() - offset;
+    TimestampMicros::new(u64::try_from(micros).unwrap_or(0))
+}
+
+/// As [`from_realtime`], for a recent timestamp taken on any clock
+pub fn from_clock(clock: ClockId, timestamp: TimeSpec) -> TimestampMicros {
+    if clock == ClockId::CLOCK_MONOTONIC_RAW {
+        let micros = u64::try_from(timestamp.num_microseconds()).unwrap_or(0);
+        return TimestampMicros::new(micros);
+    }
+
+    let clock_now = nix::time::clock_gettime(clock)
+        .expect("clock_gettime failed");
+
+    let now = now();
+
+    // how long ago the timestamp was taken. the timestamp can't be from
+    // the future, if it seems to be then the clock was stepped
+    let age = (clock_now - timestamp).num_microseconds();
+    let age = u64::try_from(age).unwrap_or(0);
+
+    TimestampMicros::new(now.get().saturating_sub(age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realtime_offset_converts_to_now() {
+        let offset = realtime_offset();
+        let realtime = nix::time::clock_gettime(ClockId::CLOCK_REALTIME).unwrap();
+
+        let converted = from_realtime_offset(offset, realtime).get();
+        let now = now().get();
+
+        // taken moments apart, converted on the same timescale
+        assert!(converted <= now);
+        assert!(now - converted < 100_000);
+    }
+
+    #[test]
+    fn realtime_offset_conversion_is_fixed() {
+        // the offset in effect when the timestamp was taken decides the
+        // conversion, not anything the realtime clock does afterwards
+        let timestamp = TimeSpec::microseconds(1_700_000_000_250_000);
+        let offset = 1_700_000_000_000_000;
+
+        assert_eq!(from_realtime_offset(offset, timestamp).get(), 250_000);
+        assert_eq!(from_realtime_offset(offset + 1000, timestamp).get(), 249_000);
+    }
 }
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use bark_protocol::packet::Volume;
+use bark_protocol::types::{ReceiverId, VolumeFlags};
+
+use crate::socket::{ProtocolSocket, SocketOpt};
+use crate::RunError;
+
+/// Volume packets aren't acknowledged, so send a few in case some are lost.
+/// Receivers treat repeats as no change
+const REPEAT: usize = 3;
+const REPEAT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(StructOpt)]
+pub struct VolumeOpt {
+    #[structopt(flatten)]
+    pub socket: SocketOpt,
+
+    /// Only change receivers on this host
+    #[structopt(long)]
+    pub host: Option<String>,
+
+    /// Only change the receiver with this id, as logged by the receiver
+    /// when it starts
+    #[structopt(long)]
+    pub receiver: Option<u64>,
+
+    /// Mute output, keeping the volume setting for when it is unmuted
+    #[structopt(long)]
+    pub mute: bool,
+
+    /// Unmute output
+    #[structopt(long, conflicts_with = "mute")]
+    pub unmute: bool,
+
+    /// Volume in percent, from 0 to 100
+    #[structopt(required_unless_one = &["mute", "unmute"])]
+    pub volume: Option<u8>,
+}
+
+pub fn run(opt: VolumeOpt) -> Result<(), RunError> {
+    if let Some(volume) = opt.volume {
+        if volume > 100 {
+            return Err(RunError::InvalidVolume(volume));
+        }
+    }
+
+    let protocol = ProtocolSocket::open(opt.socket)
+        .map_err(RunError::Listen)?;
+
+    let mut packet = Volume::allocate()
+        .expect("allocate Volume packet");
+
+    let data = packet.data_mut();
+    data.rid = opt.receiver.map(ReceiverId::new).unwrap_or(ReceiverId::broadcast());
+    data.set_hostname(opt.host.as_deref().unwrap_or_default());
+
+    if let Some(volume) = opt.volume {
+        data.volume = volume;
+        data.flags.insert(VolumeFlags::SET_VOLUME);
+    }
+
+    if opt.mute || opt.unmute {
+        data.flags.insert(VolumeFlags::SET_MUTE);
+        data.flags.set(VolumeFlags::MUTED, opt.mute);
+    }
+
+    for _ in 0..REPEAT {
+        protocol.broadcast(packet.as_packet())
+            .map_err(RunError::Send)?;
+
+        std::thread::sleep(REPEAT_INTERVAL);
+    }
+
+    Ok(())
+}